@@ -0,0 +1,116 @@
+// Times subchannel generation for a full 80-minute disc with 99 tracks,
+// which is as large as a CD gets. Each sector is also looked up from
+// scratch with sector_from_number, which is how the iterator used to find
+// them, to show how much the iterator's cursor saves.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+use cdrom::cuesheet::FileType;
+use cdrom::{Disc, Index, Track, TrackMode};
+
+// 80 minutes, not counting the 2 seconds before the first track
+const SECTOR_COUNT: i64 = 80 * 60 * 75 - 150;
+const TRACK_COUNT: i64 = 99;
+const PREGAP: i64 = 150;
+const RUNS: u32 = 5;
+
+fn disc() -> Disc {
+    let track_length = SECTOR_COUNT / TRACK_COUNT;
+    let tracks = (0..TRACK_COUNT)
+        .map(|i| {
+            let first = i * track_length;
+            let last = if i == TRACK_COUNT - 1 {
+                SECTOR_COUNT - 1
+            } else {
+                first + track_length - 1
+            };
+            // Every track but the first has a pregap
+            let start = if i == 0 { 0 } else { first + PREGAP };
+            let mut indices = vec![];
+            if i > 0 {
+                indices.push(Index {
+                    number: 0,
+                    start: first,
+                    end: start - 1,
+                });
+            }
+            indices.push(Index {
+                number: 1,
+                start,
+                end: last,
+            });
+
+            Track {
+                number: i as u8 + 1,
+                start,
+                length: last + 1 - start,
+                indices,
+                mode: TrackMode::Audio,
+                filename: "disc.bin".to_string(),
+                file_type: FileType::Binary,
+                file_offset: 0,
+                isrc: None,
+                virtual_pregap: 0,
+                virtual_postgap: 0,
+                flags: Default::default(),
+            }
+        })
+        .collect();
+
+    Disc {
+        tracks,
+        sector_count: SECTOR_COUNT,
+        cd_text: vec![],
+        catalog: None,
+        mcn_placement: Default::default(),
+        sessions: vec![],
+    }
+}
+
+// The fastest of several runs, which is the least disturbed by
+// whatever else is running.
+fn time(name: &str, run: impl Fn()) {
+    let best = (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            run();
+            started.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::ZERO);
+    println!(
+        "{:<28} {:>8.1} ms  {:>6.1} ns/sector",
+        name,
+        best.as_secs_f64() * 1000.0,
+        best.as_nanos() as f64 / SECTOR_COUNT as f64
+    );
+}
+
+fn main() {
+    let disc = disc();
+    let preconstructed = HashMap::new();
+
+    time("iterate", || {
+        for sector in disc.sectors() {
+            black_box(sector);
+        }
+    });
+    time("look up every sector", || {
+        let sectors = disc.sectors();
+        for number in 0..SECTOR_COUNT {
+            black_box(sectors.sector_from_number(number));
+        }
+    });
+    time("write subchannel", || {
+        let mut writer = BufWriter::new(io::sink());
+        for sector in disc.sectors() {
+            writer
+                .write_all(&sector.generate_subchannel(&None, &preconstructed))
+                .unwrap();
+        }
+        writer.flush().unwrap();
+    });
+}
@@ -0,0 +1,241 @@
+// Some cuesheets reference compressed audio files rather than raw audio,
+// in order to save space. The FILE command still describes them as WAVE
+// (or MP3, or FLAC), but the data inside needs to be decoded back into
+// CD-DA samples before it can be written into a raw image.
+//
+// Decoded audio is exposed as a plain reader producing the same 16-bit
+// little endian stereo samples a raw audio track would contain. CD audio
+// tracks are always made up of whole sectors, so the end of the decoded
+// audio is padded with silence if it doesn't fill the final sector.
+//
+// FLAC is always supported; OGG Vorbis and MP3 can be enabled with the
+// `ogg` and `mp3` features.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use thiserror::Error;
+
+use crate::raw::RAW_SECTOR_SIZE;
+
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[error("{0} support wasn't enabled when this program was built")]
+    Unsupported(&'static str),
+
+    #[error("audio must be 44.1kHz 16-bit stereo, but the file is {}Hz {}-bit with {} channel(s)",
+        display_option(.sample_rate), display_option(.bits_per_sample), display_option(.channels))]
+    NotCdda {
+        sample_rate: Option<u32>,
+        bits_per_sample: Option<u32>,
+        channels: Option<usize>,
+    },
+
+    #[error("the file doesn't contain any audio")]
+    NoAudio,
+
+    #[error(transparent)]
+    Decode(#[from] SymphoniaError),
+
+    #[error(transparent)]
+    IO(#[from] io::Error),
+}
+
+fn display_option<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Compressed audio formats which can be referenced by a cuesheet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressedFormat {
+    Flac,
+    Vorbis,
+    Mp3,
+}
+
+impl CompressedFormat {
+    /// Determines the format of a file referenced by a cuesheet.
+    /// The file type in the FILE command is frequently wrong, for example
+    /// WAVE for FLAC files, so we go by the extension.
+    pub fn from_path(path: &Path) -> Option<CompressedFormat> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "flac" => Some(CompressedFormat::Flac),
+            "ogg" | "oga" => Some(CompressedFormat::Vorbis),
+            "mp3" => Some(CompressedFormat::Mp3),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressedFormat::Flac => "FLAC",
+            CompressedFormat::Vorbis => "OGG Vorbis",
+            CompressedFormat::Mp3 => "MP3",
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        match self {
+            CompressedFormat::Flac => true,
+            CompressedFormat::Vorbis => cfg!(feature = "ogg"),
+            CompressedFormat::Mp3 => cfg!(feature = "mp3"),
+        }
+    }
+}
+
+/// A reader producing raw CD-DA samples from a compressed audio file.
+pub struct DecodedAudio {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    // Number of samples per channel, if the file's header records it
+    n_frames: Option<u64>,
+    kind: CompressedFormat,
+    buffer: Vec<u8>,
+    position: usize,
+    written: u64,
+    finished: bool,
+}
+
+impl DecodedAudio {
+    /// Opens a compressed audio file, checking that it decodes to CD-DA audio.
+    pub fn open(path: &Path, kind: CompressedFormat) -> Result<DecodedAudio, DecodeError> {
+        DecodedAudio::from_source(Box::new(File::open(path)?), path, kind)
+    }
+
+    /// Decodes audio from something other than a file on disk, such as a
+    /// member of an archive. `path` is only used to tell what kind of file
+    /// it is.
+    pub fn from_source(
+        source: Box<dyn MediaSource>,
+        path: &Path,
+        kind: CompressedFormat,
+    ) -> Result<DecodedAudio, DecodeError> {
+        if !kind.is_enabled() {
+            return Err(DecodeError::Unsupported(kind.name()));
+        }
+
+        let source = MediaSourceStream::new(source, Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension() {
+            hint.with_extension(&extension.to_string_lossy());
+        }
+        // Gapless playback trims the encoder delay and padding that lossy
+        // formats add, which would otherwise shift the audio out of sync
+        // with the cuesheet.
+        let format_options = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let format = symphonia::default::get_probe()
+            .format(&hint, source, &format_options, &MetadataOptions::default())?
+            .format;
+
+        let track = format.default_track().ok_or(DecodeError::NoAudio)?;
+        let params = &track.codec_params;
+        let channels = params.channels.map(|c| c.count());
+        // Lossy formats don't have a bit depth, and are always decoded
+        // to 16 bits.
+        let bits_ok = match kind {
+            CompressedFormat::Flac => params.bits_per_sample == Some(16),
+            _ => true,
+        };
+        if params.sample_rate != Some(44100) || channels != Some(2) || !bits_ok {
+            return Err(DecodeError::NotCdda {
+                sample_rate: params.sample_rate,
+                bits_per_sample: params.bits_per_sample,
+                channels,
+            });
+        }
+
+        let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+        let track_id = track.id;
+        let n_frames = params.n_frames;
+
+        Ok(DecodedAudio {
+            format,
+            decoder,
+            track_id,
+            n_frames,
+            kind,
+            buffer: vec![],
+            position: 0,
+            written: 0,
+            finished: false,
+        })
+    }
+
+    /// Calculates the length of the decoded audio in bytes, including
+    /// the padding at the end. FLAC files record their exact length in
+    /// their header; other formats need to be decoded in full to find out.
+    pub fn length(mut self) -> Result<u64, DecodeError> {
+        if let (CompressedFormat::Flac, Some(frames)) = (self.kind, self.n_frames) {
+            return Ok((frames * 4).div_ceil(RAW_SECTOR_SIZE as u64) * RAW_SECTOR_SIZE as u64);
+        }
+
+        Ok(io::copy(&mut self, &mut io::sink())?)
+    }
+
+    fn decode_next(&mut self) -> Result<(), DecodeError> {
+        let packet = match self.format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.finished = true;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != self.track_id {
+            return Ok(());
+        }
+
+        let decoded = self.decoder.decode(&packet)?;
+        let mut samples = SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+        samples.copy_interleaved_ref(decoded);
+
+        self.buffer.clear();
+        self.position = 0;
+        for sample in samples.samples() {
+            self.buffer.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for DecodedAudio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.buffer.len() {
+            if self.finished {
+                // Pad out the final sector with silence.
+                let padding = (RAW_SECTOR_SIZE as u64 - self.written % RAW_SECTOR_SIZE as u64)
+                    % RAW_SECTOR_SIZE as u64;
+                let count = buf.len().min(padding as usize);
+                buf[..count].fill(0);
+                self.written += count as u64;
+                return Ok(count);
+            }
+            self.decode_next()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+
+        let count = buf.len().min(self.buffer.len() - self.position);
+        buf[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        self.written += count as u64;
+
+        Ok(count)
+    }
+}
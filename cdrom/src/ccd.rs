@@ -0,0 +1,381 @@
+// CloneCD control files (.ccd) are INI files describing the disc's table of
+// contents. They contain the following sections:
+//
+// [CloneCD]    - the version of the format; everything in use today is 3.
+// [Disc]       - disc-wide metadata, such as the number of TOC entries and
+//                sessions, and optionally the disc's media catalog number.
+// [CDText]     - optional raw CD-TEXT packs.
+// [Session n]  - per-session metadata.
+// [Entry n]    - a raw TOC entry, as read from the Q subchannel of the leadin.
+//                Points 1 to 99 are tracks, while A0/A1/A2 hold the first
+//                track, last track and leadout of a session. Multisession
+//                discs also have B0, pointing to the next session, and C0.
+// [TRACK n]    - optional track metadata, including the mode and the location
+//                of each index, which the TOC doesn't record.
+//
+// CloneCD itself, DiscImageCreator and redumper all write slightly different
+// files: blank lines between sections may or may not be present, line endings
+// may be CRLF, hex values may be uppercase and some keys are only written by
+// some tools. We're permissive about all of that and only require the keys
+// we actually need.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CcdError {
+    #[error("missing [{0}] section")]
+    MissingSection(String),
+
+    #[error("[{section}] is missing the {key} key")]
+    MissingKey { section: String, key: String },
+
+    #[error("[{section}] has an invalid value for {key}: {value}")]
+    InvalidValue {
+        section: String,
+        key: String,
+        value: String,
+    },
+
+    #[error("the TOC doesn't contain a leadout entry")]
+    MissingLeadOut,
+
+    #[error("the TOC doesn't list any tracks")]
+    NoTracks,
+}
+
+/// A raw table of contents entry from an [Entry n] section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TocEntry {
+    pub session: u8,
+    pub point: u8,
+    pub adr: u8,
+    pub control: u8,
+    pub track_no: u8,
+    pub amin: u8,
+    pub asec: u8,
+    pub aframe: u8,
+    pub alba: i64,
+    pub zero: u8,
+    pub pmin: u8,
+    pub psec: u8,
+    pub pframe: u8,
+    pub plba: i64,
+}
+
+/// Metadata from a [Session n] section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CcdSession {
+    pub number: u8,
+    pub pregap_mode: u8,
+    pub pregap_subc: u8,
+}
+
+/// Metadata from a [TRACK n] section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CcdTrack {
+    pub number: u8,
+    // 0 for audio, otherwise the data mode
+    pub mode: u8,
+    // Index number and start sector of every index in the track
+    pub indices: Vec<(u8, i64)>,
+    pub isrc: Option<String>,
+    // Flags such as DCP or 4CH, as written in the FLAGS key
+    pub flags: Vec<String>,
+}
+
+/// A parsed CloneCD control file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ccd {
+    pub version: u32,
+    pub sessions: Vec<CcdSession>,
+    pub data_tracks_scrambled: bool,
+    pub catalog: Option<String>,
+    pub entries: Vec<TocEntry>,
+    pub tracks: Vec<CcdTrack>,
+}
+
+struct Section {
+    // The section's name as written, used for error messages
+    name: String,
+    values: HashMap<String, String>,
+}
+
+impl Section {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .get(&key.to_ascii_lowercase())
+            .map(|v| v.as_str())
+    }
+
+    fn int<T: TryFrom<i64>>(&self, key: &str) -> Result<Option<T>, CcdError> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        let parsed = if let Some(hex) = value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+        {
+            i64::from_str_radix(hex, 16).ok()
+        } else {
+            value.parse().ok()
+        };
+
+        match parsed.and_then(|v| T::try_from(v).ok()) {
+            Some(v) => Ok(Some(v)),
+            None => Err(CcdError::InvalidValue {
+                section: self.name.clone(),
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+        }
+    }
+
+    fn required_int<T: TryFrom<i64>>(&self, key: &str) -> Result<T, CcdError> {
+        self.int(key)?.ok_or_else(|| CcdError::MissingKey {
+            section: self.name.clone(),
+            key: key.to_string(),
+        })
+    }
+
+    // Splits a name such as "Entry 12" into its kind and number.
+    fn numbered(&self, kind: &str) -> Option<&str> {
+        let (name, number) = self.name.split_once(char::is_whitespace)?;
+        if name.eq_ignore_ascii_case(kind) {
+            Some(number.trim())
+        } else {
+            None
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&self, number: &str) -> Result<T, CcdError> {
+        number.parse().map_err(|_| CcdError::InvalidValue {
+            section: self.name.clone(),
+            key: "section number".to_string(),
+            value: number.to_string(),
+        })
+    }
+}
+
+fn parse_sections(text: &str) -> Vec<Section> {
+    let mut sections: Vec<Section> = vec![];
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push(Section {
+                name: name.trim().to_string(),
+                values: HashMap::new(),
+            });
+        } else if let (Some((key, value)), Some(section)) =
+            (line.split_once('='), sections.last_mut())
+        {
+            section
+                .values
+                .insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    sections
+}
+
+impl Ccd {
+    pub fn parse(text: &str) -> Result<Ccd, CcdError> {
+        let sections = parse_sections(text);
+        let find = |name: &str| sections.iter().find(|s| s.name.eq_ignore_ascii_case(name));
+
+        let version = find("CloneCD")
+            .ok_or_else(|| CcdError::MissingSection("CloneCD".to_string()))?
+            .int("Version")?
+            .unwrap_or(3);
+
+        let (data_tracks_scrambled, catalog) = match find("Disc") {
+            Some(disc) => (
+                disc.int::<u8>("DataTracksScrambled")?.unwrap_or(0) != 0,
+                disc.get("CATALOG")
+                    .filter(|c| !c.is_empty())
+                    .map(|c| c.to_string()),
+            ),
+            None => (false, None),
+        };
+
+        let mut sessions = vec![];
+        let mut entries = vec![];
+        let mut tracks = vec![];
+        for section in &sections {
+            if let Some(number) = section.numbered("Session") {
+                sessions.push(CcdSession {
+                    number: section.number(number)?,
+                    pregap_mode: section.int("PreGapMode")?.unwrap_or(0),
+                    pregap_subc: section.int("PreGapSubC")?.unwrap_or(0),
+                });
+            } else if section.numbered("Entry").is_some() {
+                entries.push(TocEntry {
+                    session: section.int("Session")?.unwrap_or(1),
+                    point: section.required_int("Point")?,
+                    adr: section.int("ADR")?.unwrap_or(1),
+                    control: section.required_int("Control")?,
+                    track_no: section.int("TrackNo")?.unwrap_or(0),
+                    amin: section.int("AMin")?.unwrap_or(0),
+                    asec: section.int("ASec")?.unwrap_or(0),
+                    aframe: section.int("AFrame")?.unwrap_or(0),
+                    alba: section.int("ALBA")?.unwrap_or(-150),
+                    zero: section.int("Zero")?.unwrap_or(0),
+                    pmin: section.required_int("PMin")?,
+                    psec: section.required_int("PSec")?,
+                    pframe: section.required_int("PFrame")?,
+                    // Every tool writes PLBA, but it's redundant with
+                    // the MSF fields, so fall back to those if it's missing.
+                    plba: match section.int("PLBA")? {
+                        Some(plba) => plba,
+                        None => {
+                            section.required_int::<i64>("PMin")? * 4500
+                                + section.required_int::<i64>("PSec")? * 75
+                                + section.required_int::<i64>("PFrame")?
+                                - 150
+                        }
+                    },
+                });
+            } else if let Some(number) = section.numbered("TRACK") {
+                let mut indices = vec![];
+                for i in 0..100 {
+                    if let Some(start) = section.int(&format!("INDEX {}", i))? {
+                        indices.push((i, start));
+                    }
+                }
+                tracks.push(CcdTrack {
+                    number: section.number(number)?,
+                    mode: section.required_int("MODE")?,
+                    indices,
+                    isrc: section
+                        .get("ISRC")
+                        .filter(|i| !i.is_empty())
+                        .map(|i| i.to_string()),
+                    flags: section
+                        .get("FLAGS")
+                        .map(|f| f.split_whitespace().map(|f| f.to_string()).collect())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+
+        Ok(Ccd {
+            version,
+            sessions,
+            data_tracks_scrambled,
+            catalog,
+            entries,
+            tracks,
+        })
+    }
+
+    /// The first sector of the leadout, which is also the number of
+    /// sectors in the image.
+    pub fn leadout(&self) -> Option<i64> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.point == 0xA2)
+            .map(|entry| entry.plba)
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ccd, CcdError};
+    use crate::{Disc, TrackMode};
+
+    // Written the way DiscImageCreator does: no blank lines, CRLF line
+    // endings, uppercase hex and extra disc metadata.
+    const DISC_IMAGE_CREATOR: &str = "[CloneCD]\r\nVersion=3\r\n[Disc]\r\nTocEntries=5\r\nSessions=1\r\nDataTracksScrambled=0\r\nCDTextLength=0\r\nCATALOG=0123456789012\r\n[Session 1]\r\nPreGapMode=2\r\nPreGapSubC=0\r\n[Entry 0]\r\nSession=1\r\nPoint=0xA0\r\nADR=0x01\r\nControl=0x04\r\nTrackNo=0\r\nAMin=0\r\nASec=0\r\nAFrame=0\r\nALBA=-150\r\nZero=0\r\nPMin=1\r\nPSec=32\r\nPFrame=0\r\nPLBA=4350\r\n[Entry 1]\r\nSession=1\r\nPoint=0xA1\r\nADR=0x01\r\nControl=0x00\r\nTrackNo=0\r\nAMin=0\r\nASec=0\r\nAFrame=0\r\nALBA=-150\r\nZero=0\r\nPMin=2\r\nPSec=0\r\nPFrame=0\r\nPLBA=8850\r\n[Entry 2]\r\nSession=1\r\nPoint=0xA2\r\nADR=0x01\r\nControl=0x00\r\nTrackNo=0\r\nAMin=0\r\nASec=0\r\nAFrame=0\r\nALBA=-150\r\nZero=0\r\nPMin=0\r\nPSec=12\r\nPFrame=0\r\nPLBA=750\r\n[Entry 3]\r\nSession=1\r\nPoint=0x01\r\nADR=0x01\r\nControl=0x04\r\nTrackNo=0\r\nAMin=0\r\nASec=0\r\nAFrame=0\r\nALBA=-150\r\nZero=0\r\nPMin=0\r\nPSec=2\r\nPFrame=0\r\nPLBA=0\r\n[Entry 4]\r\nSession=1\r\nPoint=0x02\r\nADR=0x01\r\nControl=0x00\r\nTrackNo=0\r\nAMin=0\r\nASec=0\r\nAFrame=0\r\nALBA=-150\r\nZero=0\r\nPMin=0\r\nPSec=8\r\nPFrame=0\r\nPLBA=450\r\n[TRACK 1]\r\nMODE=2\r\nINDEX 1=0\r\n[TRACK 2]\r\nMODE=0\r\nISRC=USABC1234567\r\nFLAGS= DCP PRE\r\nINDEX 0=300\r\nINDEX 1=450\r\n";
+
+    // An older CloneCD file without [TRACK n] sections or PLBA keys.
+    const ENTRIES_ONLY: &str = "[CloneCD]
+Version=3
+
+[Disc]
+TocEntries=3
+
+[Session 1]
+PreGapMode=1
+
+[Entry 0]
+Point=0xa2
+Control=0x04
+PMin=0
+PSec=12
+PFrame=0
+
+[Entry 1]
+Point=0x01
+Control=0x04
+PMin=0
+PSec=2
+PFrame=0
+
+[Entry 2]
+Point=0x02
+Control=0x00
+PMin=0
+PSec=8
+PFrame=0
+";
+
+    #[test]
+    fn test_parse_disc_image_creator() {
+        let ccd = Ccd::parse(DISC_IMAGE_CREATOR).unwrap();
+
+        assert_eq!(ccd.catalog.as_deref(), Some("0123456789012"));
+        assert_eq!(ccd.entries.len(), 5);
+        assert_eq!(ccd.entries[0].point, 0xA0);
+        assert_eq!(ccd.leadout(), Some(750));
+        assert_eq!(ccd.tracks[1].isrc.as_deref(), Some("USABC1234567"));
+        assert_eq!(ccd.tracks[1].flags, vec!["DCP", "PRE"]);
+
+        let disc = Disc::from_ccd(&ccd).unwrap();
+        assert_eq!(disc.sector_count, 750);
+        assert!(matches!(disc.tracks[0].mode, TrackMode::Mode2Raw));
+        assert!(matches!(disc.tracks[1].mode, TrackMode::Audio));
+        assert_eq!(disc.tracks[0].length, 300);
+        assert_eq!(disc.tracks[1].start, 450);
+        assert!(disc.tracks[1].flags.copy_permitted && disc.tracks[1].flags.pre_emphasis);
+        assert_eq!(disc.tracks[1].length, 300);
+        assert_eq!(disc.tracks[1].indices[0].start, 300);
+        assert_eq!(disc.tracks[1].indices[0].end, 449);
+    }
+
+    #[test]
+    fn test_parse_entries_only() {
+        let ccd = Ccd::parse(ENTRIES_ONLY).unwrap();
+        assert_eq!(ccd.leadout(), Some(750));
+
+        let disc = Disc::from_ccd(&ccd).unwrap();
+        assert_eq!(disc.tracks.len(), 2);
+        assert!(matches!(disc.tracks[0].mode, TrackMode::Mode1Raw));
+        assert!(matches!(disc.tracks[1].mode, TrackMode::Audio));
+        assert_eq!(disc.tracks[1].start, 450);
+        assert_eq!(disc.tracks[1].indices.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Ccd::parse("[Disc]\nSessions=1\n").unwrap_err(),
+            CcdError::MissingSection("CloneCD".to_string())
+        );
+        assert_eq!(
+            Ccd::parse("[CloneCD]\nVersion=3\n[Entry 0]\nPoint=0xzz\n").unwrap_err(),
+            CcdError::InvalidValue {
+                section: "Entry 0".to_string(),
+                key: "Point".to_string(),
+                value: "0xzz".to_string(),
+            }
+        );
+
+        let ccd = Ccd::parse("[CloneCD]\nVersion=3\n").unwrap();
+        assert_eq!(Disc::from_ccd(&ccd).err(), Some(CcdError::MissingLeadOut));
+    }
+}
@@ -0,0 +1,416 @@
+// CD-TEXT stores album and track metadata, such as titles and performers,
+// in the R-W subchannel of the disc's lead-in. The lead-in isn't part of a
+// CloneCD image's .sub, so CloneCD instead stores the raw CD-TEXT packs in
+// the [CDText] section of the .ccd, and a writer puts them in the lead-in
+// when burning.
+//
+// CD-TEXT is made up of 18-byte packs:
+//
+// 0      - Pack type, such as 0x80 for titles
+// 1      - Track number the first character in this pack belongs to;
+//          0 is the album
+// 2      - Sequence number, counting every pack in order
+// 3      - Bit 7 is set for double-byte text; bits 4-6 are the block number,
+//          and bits 0-3 are how many characters of the current string came
+//          before this pack, up to 15
+// 4-15   - Text
+// 16-17  - CRC, calculated the same way as in the Q subchannel
+//
+// Each pack type holds one null-terminated string for the album and then
+// one for each track, run together and split across as many packs as
+// needed. At the end come three size information packs which describe
+// the rest of the block.
+//
+// Only a single block, in a single language, is supported.
+
+use std::path::Path;
+
+use encoding_rs::SHIFT_JIS;
+use thiserror::Error;
+
+use crate::cuesheet::Cuesheet;
+use crate::source::FileSource;
+use crate::{crc16, CRC16_INITIAL_CRC};
+
+pub const PACK_SIZE: usize = 18;
+const TEXT_SIZE: usize = 12;
+
+pub type Pack = [u8; PACK_SIZE];
+
+const SIZE_INFO: u8 = 0x8F;
+// Sequence numbers are a single byte
+const MAX_PACKS: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum CdTextError {
+    #[error("\"{0}\" can't be represented in either ISO-8859-1 or Shift-JIS")]
+    Unencodable(String),
+    #[error("the CD-TEXT needs {0} packs, but a block can only hold 256")]
+    TooManyPacks(usize),
+    #[error("the CD-TEXT file is {0} bytes long, which isn't a whole number of packs")]
+    InvalidFileLength(usize),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+// The text pack types which can be set from a cuesheet, in the order
+// they're written: title, performer, songwriter, composer, arranger and
+// message.
+const TEXT_FIELDS: [u8; 6] = [0x80, 0x81, 0x82, 0x83, 0x84, 0x85];
+
+/// The pack type for a cuesheet's CD-TEXT command, such as TITLE.
+pub fn pack_type(command: &str) -> Option<u8> {
+    let pack_type = match command.to_ascii_uppercase().as_str() {
+        "TITLE" => 0x80,
+        "PERFORMER" => 0x81,
+        "SONGWRITER" => 0x82,
+        "COMPOSER" => 0x83,
+        "ARRANGER" => 0x84,
+        "MESSAGE" => 0x85,
+        _ => return None,
+    };
+    Some(pack_type)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CharacterSet {
+    Iso8859_1,
+    ShiftJis,
+}
+
+impl CharacterSet {
+    fn code(&self) -> u8 {
+        match self {
+            CharacterSet::Iso8859_1 => 0x00,
+            CharacterSet::ShiftJis => 0x80,
+        }
+    }
+
+    fn language(&self) -> u8 {
+        match self {
+            // English
+            CharacterSet::Iso8859_1 => 0x09,
+            // Japanese
+            CharacterSet::ShiftJis => 0x69,
+        }
+    }
+
+    fn is_double_byte(&self) -> bool {
+        *self == CharacterSet::ShiftJis
+    }
+
+    fn encode(&self, text: &str) -> Result<Vec<u8>, CdTextError> {
+        match self {
+            // ISO-8859-1 maps directly onto the first 256 code points
+            CharacterSet::Iso8859_1 => Ok(text.chars().map(|c| c as u8).collect()),
+            CharacterSet::ShiftJis => {
+                let (encoded, _, had_errors) = SHIFT_JIS.encode(text);
+                if had_errors {
+                    return Err(CdTextError::Unencodable(text.to_string()));
+                }
+                Ok(encoded.into_owned())
+            }
+        }
+    }
+
+    fn terminator(&self) -> &'static [u8] {
+        if self.is_double_byte() {
+            &[0, 0]
+        } else {
+            &[0]
+        }
+    }
+}
+
+/// The CD-TEXT strings for the album or a single track.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CdTextFields {
+    /// Strings keyed by pack type
+    pub text: Vec<(u8, String)>,
+}
+
+impl CdTextFields {
+    fn get(&self, pack_type: u8) -> Option<&str> {
+        self.text
+            .iter()
+            .find(|(t, _)| *t == pack_type)
+            .map(|(_, text)| text.as_str())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+}
+
+/// CD-TEXT for a whole disc, with tracks numbered from 1.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CdText {
+    pub album: CdTextFields,
+    pub tracks: Vec<CdTextFields>,
+}
+
+impl CdText {
+    pub fn from_cuesheet(cuesheet: &Cuesheet) -> CdText {
+        CdText {
+            album: cuesheet.cd_text.clone(),
+            tracks: cuesheet
+                .tracks
+                .iter()
+                .map(|track| track.cd_text.clone())
+                .collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.album.is_empty() && self.tracks.iter().all(CdTextFields::is_empty)
+    }
+
+    // Everything fits in ISO-8859-1 for most discs; Shift-JIS is the only
+    // other character set with wide support.
+    fn character_set(&self) -> CharacterSet {
+        let latin1 = std::iter::once(&self.album)
+            .chain(&self.tracks)
+            .flat_map(|fields| &fields.text)
+            .all(|(_, text)| text.chars().all(|c| (c as u32) <= 0xFF));
+        if latin1 {
+            CharacterSet::Iso8859_1
+        } else {
+            CharacterSet::ShiftJis
+        }
+    }
+
+    /// Encodes the CD-TEXT into packs, including the size information
+    /// packs at the end. Returns no packs at all if there's no text.
+    pub fn to_packs(&self) -> Result<Vec<Pack>, CdTextError> {
+        if self.is_empty() {
+            return Ok(vec![]);
+        }
+        let charset = self.character_set();
+        let flags = if charset.is_double_byte() { 0x80 } else { 0 };
+
+        let mut packs: Vec<Pack> = vec![];
+        let mut pack_counts = [0usize; 16];
+        for pack_type in TEXT_FIELDS {
+            let strings = std::iter::once(&self.album)
+                .chain(&self.tracks)
+                .map(|fields| charset.encode(fields.get(pack_type).unwrap_or_default()))
+                .collect::<Result<Vec<_>, _>>()?;
+            if strings.iter().all(Vec::is_empty) {
+                continue;
+            }
+
+            // For every byte of the run-together text, which string it
+            // belongs to and how many characters of it come before it.
+            let char_size = if charset.is_double_byte() { 2 } else { 1 };
+            let mut text = vec![];
+            let mut owners = vec![];
+            for (track, string) in strings.iter().enumerate() {
+                let terminated = [string.as_slice(), charset.terminator()].concat();
+                for i in 0..terminated.len() {
+                    owners.push((track as u8, (i / char_size).min(15) as u8));
+                }
+                text.extend(terminated);
+            }
+
+            for (chunk, owner) in text.chunks(TEXT_SIZE).zip(owners.chunks(TEXT_SIZE)) {
+                let (track, position) = owner[0];
+                let mut pack = [0; PACK_SIZE];
+                pack[0] = pack_type;
+                pack[1] = track;
+                pack[3] = flags | position;
+                pack[4..4 + chunk.len()].copy_from_slice(chunk);
+                packs.push(pack);
+                pack_counts[(pack_type & 0x0F) as usize] += 1;
+            }
+        }
+
+        let total = packs.len() + 3;
+        if total > MAX_PACKS {
+            return Err(CdTextError::TooManyPacks(total));
+        }
+        pack_counts[(SIZE_INFO & 0x0F) as usize] = 3;
+        // Every count fits in a byte now that the total does
+        let pack_counts = pack_counts.map(|count| count as u8);
+
+        let mut size_info = [0u8; 36];
+        size_info[0] = charset.code();
+        size_info[1] = 1;
+        size_info[2] = self.tracks.len() as u8;
+        size_info[4..20].copy_from_slice(&pack_counts);
+        // Last sequence number in block 0
+        size_info[20] = (total - 1) as u8;
+        // Language of block 0
+        size_info[28] = charset.language();
+        for (i, chunk) in size_info.chunks(TEXT_SIZE).enumerate() {
+            let mut pack = [0; PACK_SIZE];
+            pack[0] = SIZE_INFO;
+            pack[1] = i as u8;
+            pack[3] = flags;
+            pack[4..16].copy_from_slice(chunk);
+            packs.push(pack);
+        }
+
+        for (sequence, pack) in packs.iter_mut().enumerate() {
+            pack[2] = sequence as u8;
+            let crc = crc16(&pack[0..16], CRC16_INITIAL_CRC);
+            pack[16] = ((crc >> 8) & 0xFF) as u8;
+            pack[17] = (crc & 0xFF) as u8;
+        }
+
+        Ok(packs)
+    }
+}
+
+/// Reads the packs from a binary CD-TEXT file, as referenced by a
+/// cuesheet's CDTEXTFILE. These may start with a 4-byte header giving the
+/// length, as written by cdrecord and most other tools, and may have a
+/// trailing null byte.
+pub fn read_cdtext_file(data: &[u8]) -> Result<Vec<Pack>, CdTextError> {
+    let packs = match data.len() % PACK_SIZE {
+        0 => data,
+        4 => &data[4..],
+        5 => &data[4..data.len() - 1],
+        _ => return Err(CdTextError::InvalidFileLength(data.len())),
+    };
+
+    Ok(packs
+        .chunks(PACK_SIZE)
+        .map(|chunk| chunk.try_into().expect("chunks are always whole packs"))
+        .collect())
+}
+
+/// Builds the CD-TEXT packs for a cuesheet, either from a CDTEXTFILE or
+/// from the TITLE, PERFORMER and similar commands. `files` is where the
+/// cuesheet's files, including any CDTEXTFILE, are read from.
+pub fn packs_from_cuesheet(
+    cuesheet: &Cuesheet,
+    files: &FileSource,
+) -> Result<Vec<Pack>, CdTextError> {
+    if let Some(filename) = &cuesheet.cdtextfile {
+        return read_cdtext_file(&files.read(Path::new(filename))?);
+    }
+
+    CdText::from_cuesheet(cuesheet).to_packs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_cdtext_file, CdText, CdTextError, CdTextFields, PACK_SIZE, TEXT_SIZE};
+    use crate::{crc16, CRC16_INITIAL_CRC};
+
+    fn fields(text: &[(u8, &str)]) -> CdTextFields {
+        CdTextFields {
+            text: text.iter().map(|(t, s)| (*t, s.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn test_latin1_packs() {
+        let cd_text = CdText {
+            album: fields(&[(0x80, "Album Title"), (0x81, "Artist")]),
+            tracks: vec![fields(&[(0x80, "First")]), fields(&[(0x80, "Señor")])],
+        };
+        let packs = cd_text.to_packs().unwrap();
+
+        // "Album Title\0First\0Señor\0" is 24 bytes, "Artist\0\0\0" is 9
+        assert_eq!(packs.len(), 2 + 1 + 3);
+        assert_eq!(&packs[0][0..4], &[0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(&packs[0][4..16], b"Album Title\0");
+        // Starts with track 1's title, from its first character
+        assert_eq!(&packs[1][0..4], &[0x80, 0x01, 0x01, 0x00]);
+        assert_eq!(&packs[1][4..16], b"First\0Se\xF1or\0");
+        // Tracks without a performer get an empty string
+        assert_eq!(&packs[2][4..16], b"Artist\0\0\0\0\0\0");
+
+        let size_info = packs[3..]
+            .iter()
+            .flat_map(|p| p[4..16].to_vec())
+            .collect::<Vec<_>>();
+        // ISO-8859-1, tracks 1-2
+        assert_eq!(&size_info[0..3], &[0x00, 1, 2]);
+        // Two title packs, one performer pack, three size packs
+        assert_eq!(size_info[4], 2);
+        assert_eq!(size_info[5], 1);
+        assert_eq!(size_info[19], 3);
+        assert_eq!(size_info[20], 5);
+        assert_eq!(size_info[28], 0x09);
+
+        for (i, pack) in packs.iter().enumerate() {
+            assert_eq!(pack[2], i as u8);
+            let crc = crc16(&pack[0..16], CRC16_INITIAL_CRC);
+            assert_eq!(&pack[16..18], &crc.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn test_character_position() {
+        let cd_text = CdText {
+            album: fields(&[(0x80, "A very long album title")]),
+            tracks: vec![fields(&[(0x80, "Track")])],
+        };
+        let packs = cd_text.to_packs().unwrap();
+        // The second pack continues the album title from its 13th character
+        assert_eq!(&packs[1][0..4], &[0x80, 0x00, 0x01, 12]);
+        assert_eq!(&packs[1][4..16], b"album title\0");
+        assert_eq!(&packs[2][0..4], &[0x80, 0x01, 0x02, 0]);
+    }
+
+    #[test]
+    fn test_too_many_packs() {
+        // A single field needing more packs than a count can hold
+        let cd_text = CdText {
+            album: fields(&[(0x80, &"x".repeat(TEXT_SIZE * 300))]),
+            tracks: vec![],
+        };
+        assert!(matches!(
+            cd_text.to_packs(),
+            Err(CdTextError::TooManyPacks(304))
+        ));
+    }
+
+    #[test]
+    fn test_shift_jis_packs() {
+        let cd_text = CdText {
+            album: fields(&[(0x80, "日本")]),
+            tracks: vec![fields(&[(0x80, "歌")])],
+        };
+        let packs = cd_text.to_packs().unwrap();
+        assert_eq!(&packs[0][0..4], &[0x80, 0x00, 0x00, 0x80]);
+        assert_eq!(
+            &packs[0][4..16],
+            &[0x93, 0xFA, 0x96, 0x7B, 0, 0, 0x89, 0xCC, 0, 0, 0, 0]
+        );
+        // MS-JIS, in Japanese
+        assert_eq!(packs[1][4], 0x80);
+        assert_eq!(packs[3][8], 0x69);
+
+        let cd_text = CdText {
+            album: fields(&[(0x80, "😀")]),
+            tracks: vec![],
+        };
+        assert!(matches!(
+            cd_text.to_packs(),
+            Err(CdTextError::Unencodable(text)) if text == "😀"
+        ));
+    }
+
+    #[test]
+    fn test_read_cdtext_file() {
+        let packs = CdText {
+            album: fields(&[(0x80, "Album")]),
+            tracks: vec![fields(&[])],
+        }
+        .to_packs()
+        .unwrap();
+        let raw = packs.concat();
+
+        assert_eq!(read_cdtext_file(&raw).unwrap(), packs);
+        let length = ((raw.len() + 2) as u16).to_be_bytes();
+        let with_header = [&[length[0], length[1], 0, 0], &raw[..], &[0]].concat();
+        assert_eq!(read_cdtext_file(&with_header).unwrap(), packs);
+        assert!(matches!(
+            read_cdtext_file(&raw[..PACK_SIZE + 1]),
+            Err(CdTextError::InvalidFileLength(19))
+        ));
+    }
+}
@@ -0,0 +1,1061 @@
+// Converting a BIN/CUE disc image into a CloneCD image. This ties the rest
+// of the crate together: the cuesheet and the files it references are read
+// into a Disc, any LSD or SBI next to it is used to work out which copy
+// protection to recreate, and the .sub, .img and .ccd are written out.
+// The BIN/CUE can also be inside a ZIP archive, in which case it's read
+// from there without being extracted.
+//
+// Nothing here prints anything; everything the caller might want to tell
+// the user about is returned in the ConversionReport instead, so frontends
+// other than the cue2ccd binary can present it however they like.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::audio::{CompressedFormat, DecodeError, DecodedAudio};
+use crate::cdtext::{self, CdTextError};
+use crate::cuesheet::{CueError, CueFile, Cuesheet, FileType};
+use crate::protection::{detect_protection, Candidate, Confidence};
+use crate::qmode::{Isrc, Mcn, Placement, QModeError};
+use crate::session::{self, SessionError};
+use crate::sidecar::{self, Sidecar, SidecarError, Validation};
+use crate::source::{self, FileSource, ZipError};
+use crate::wave::{self, WaveData, WaveError};
+use crate::{lba_to_msf, Disc, DiscProtection};
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum ConvertError {
+    #[error("Couldn't find one or more files specified in the cuesheet: {}", missing_files.join(", "))]
+    MissingFiles { missing_files: Vec<String> },
+
+    #[error("Unable to determine the directory {filename} is in")]
+    NoParent { filename: String },
+
+    #[error("Unable to determine the filename portion of {filename}")]
+    NoFilename { filename: String },
+
+    #[error("Couldn't find {filename}")]
+    MissingSidecar { filename: String },
+
+    // Thrown if SBI file exists but doesn't have the correct SBI header
+    #[error("Invalid SBI file")]
+    InvalidSbi,
+
+    #[error("{filename} is damaged")]
+    #[diagnostic(help(
+        "Pass --lenient to use it anyway, skipping over or zero-filling the damaged entries."
+    ))]
+    InvalidSidecar {
+        filename: String,
+        source: SidecarError,
+    },
+
+    #[error("The data in the {sidecar} doesn't match {chosen}")]
+    #[diagnostic(help(
+        "{explanation} Choose a different --protection-type, or leave it out to use the \
+         {sidecar} as-is."
+    ))]
+    ProtectionConflict {
+        sidecar: String,
+        chosen: String,
+        /// What the data looks like instead
+        explanation: String,
+    },
+
+    #[error("The cuesheet's CD-TEXT couldn't be encoded")]
+    #[diagnostic(help(
+        "CD-TEXT can only hold text in ISO-8859-1 or Shift-JIS, and around 3KB of it."
+    ))]
+    InvalidCdText { source: CdTextError },
+
+    #[error("The cuesheet's CATALOG is invalid")]
+    #[diagnostic(help("A media catalog number is a 13-digit UPC or EAN barcode."))]
+    InvalidCatalog { source: QModeError },
+
+    #[error("The cuesheet's sessions are invalid")]
+    #[diagnostic(help(
+        "Sessions are marked with REM SESSION, numbered in order from 01, and each needs at least one track."
+    ))]
+    InvalidSessions { source: SessionError },
+
+    #[error("Track {track}'s ISRC is invalid")]
+    #[diagnostic(help("An ISRC is 12 characters long, without dashes, such as USABC1234567."))]
+    InvalidIsrc { track: usize, source: QModeError },
+
+    #[error("{filename} isn't a supported WAVE file")]
+    #[diagnostic(help("WAVE files must contain uncompressed 44.1kHz 16-bit stereo audio."))]
+    InvalidWaveFile { filename: String, source: WaveError },
+
+    #[error("{filename} is an {} file, which isn't supported", file_type.as_cue_str())]
+    #[diagnostic(help(
+        "Convert it to a WAVE file and point the cuesheet's FILE command at that."
+    ))]
+    UnsupportedFileType {
+        filename: String,
+        file_type: FileType,
+    },
+
+    #[error("{filename} couldn't be decoded")]
+    #[diagnostic(help("Compressed audio files must decode to 44.1kHz 16-bit stereo audio."))]
+    InvalidAudioFile {
+        filename: String,
+        source: DecodeError,
+    },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("{filename} isn't a valid cuesheet")]
+    InvalidCuesheet {
+        filename: String,
+        #[diagnostic_source]
+        source: CueError,
+    },
+
+    #[error("{filename} isn't a valid ZIP archive")]
+    InvalidArchive { filename: String, source: ZipError },
+
+    #[error("{filename} doesn't contain any cuesheets")]
+    NoCuesheetInArchive { filename: String },
+
+    #[error("{filename} contains more than one cuesheet")]
+    #[diagnostic(help(
+        "Choose which one to convert with --cuesheet: {}",
+        cuesheets.join(", ")
+    ))]
+    SeveralCuesheetsInArchive {
+        filename: String,
+        cuesheets: Vec<String>,
+    },
+
+    #[error("{filename} doesn't contain {cuesheet}")]
+    #[diagnostic(help("The cuesheets in it are: {}", cuesheets.join(", ")))]
+    MissingCuesheetInArchive {
+        filename: String,
+        cuesheet: String,
+        cuesheets: Vec<String>,
+    },
+}
+
+/// Something that didn't stop the conversion, but which the user should
+/// probably hear about.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ConversionWarning {
+    #[error("{filename}: {warning}")]
+    DamagedSidecar {
+        filename: String,
+        warning: SidecarError,
+    },
+
+    #[error("the LSD and SBI disagree about sector {}; using the LSD", format_msf(*sector))]
+    SidecarConflict { sector: i64 },
+
+    #[error(
+        "the {sidecar} might be for {}, but it doesn't quite match: {}",
+        candidate.protection,
+        candidate.reason
+    )]
+    UncertainProtection {
+        sidecar: String,
+        candidate: Candidate,
+    },
+
+    #[error(
+        "there's no LSD or SBI and no LibCrypt key was given, so the key will probably be wrong"
+    )]
+    MissingLibCryptKey,
+}
+
+fn format_msf(sector: i64) -> String {
+    let (m, s, f) = lba_to_msf(sector);
+    format!("{:02}:{:02}:{:02}", m, s, f)
+}
+
+/// A family of copy protection to recreate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtectionType {
+    DiscGuard,
+    LibCrypt,
+}
+
+impl ProtectionType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProtectionType::DiscGuard => "DiscGuard",
+            ProtectionType::LibCrypt => "LibCrypt",
+        }
+    }
+
+    /// Whether a specific protection belongs to this family.
+    pub fn includes(&self, protection: &DiscProtection) -> bool {
+        match self {
+            ProtectionType::DiscGuard => matches!(
+                protection,
+                DiscProtection::DiscGuardScheme1 | DiscProtection::DiscGuardScheme2
+            ),
+            ProtectionType::LibCrypt => matches!(
+                protection,
+                DiscProtection::LibCryptScheme1 { .. } | DiscProtection::LibCryptScheme2 { .. }
+            ),
+        }
+    }
+}
+
+/// What to do about the .img.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImgPolicy {
+    /// Write the .img, unless one already exists
+    #[default]
+    KeepExisting,
+    /// Write the .img, replacing any that already exists
+    Overwrite,
+    /// Don't write the .img at all
+    Skip,
+}
+
+/// Where LSD and SBI files come from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Sidecars {
+    /// Look next to the cuesheet for files with the same name, in any case
+    #[default]
+    Find,
+    /// Only use these files
+    Given {
+        lsd: Option<PathBuf>,
+        sbi: Option<PathBuf>,
+    },
+    /// Don't use any
+    Ignore,
+}
+
+/// Everything that controls a conversion. Only the cuesheet, or a ZIP
+/// archive containing it, is required; everything else defaults to what
+/// the cue2ccd binary does without any options.
+#[derive(Clone, Debug)]
+pub struct ConversionOptions {
+    cue_path: PathBuf,
+    archive_cuesheet: Option<String>,
+    output_dir: Option<PathBuf>,
+    img_policy: ImgPolicy,
+    protection_type: Option<ProtectionType>,
+    libcrypt_key: Option<u16>,
+    sidecars: Sidecars,
+    lenient: bool,
+    mcn_placement: Placement,
+}
+
+impl ConversionOptions {
+    pub fn new<P: Into<PathBuf>>(cue_path: P) -> ConversionOptions {
+        ConversionOptions {
+            cue_path: cue_path.into(),
+            archive_cuesheet: None,
+            output_dir: None,
+            img_policy: ImgPolicy::default(),
+            protection_type: None,
+            libcrypt_key: None,
+            sidecars: Sidecars::default(),
+            lenient: false,
+            mcn_placement: Placement::default(),
+        }
+    }
+
+    /// Which cuesheet to convert when the cuesheet path is a ZIP archive
+    /// containing several, either by its path within the archive or by
+    /// just its filename.
+    pub fn archive_cuesheet<S: Into<String>>(mut self, cuesheet: S) -> ConversionOptions {
+        self.archive_cuesheet = Some(cuesheet.into());
+        self
+    }
+
+    /// Where to write the CloneCD image; defaults to next to the cuesheet,
+    /// or to next to the ZIP archive it's in.
+    pub fn output_dir<P: Into<PathBuf>>(mut self, output_dir: P) -> ConversionOptions {
+        self.output_dir = Some(output_dir.into());
+        self
+    }
+
+    pub fn img_policy(mut self, img_policy: ImgPolicy) -> ConversionOptions {
+        self.img_policy = img_policy;
+        self
+    }
+
+    /// The protection to recreate. If there's an LSD or SBI, its data has
+    /// to match this; otherwise the protection is generated from scratch.
+    pub fn protection_type(mut self, protection_type: ProtectionType) -> ConversionOptions {
+        self.protection_type = Some(protection_type);
+        self
+    }
+
+    /// The 16-bit LibCrypt key, for use when there's no LSD or SBI.
+    pub fn libcrypt_key(mut self, key: u16) -> ConversionOptions {
+        self.libcrypt_key = Some(key);
+        self
+    }
+
+    pub fn sidecars(mut self, sidecars: Sidecars) -> ConversionOptions {
+        self.sidecars = sidecars;
+        self
+    }
+
+    /// Use damaged LSD and SBI files anyway, with warnings about their
+    /// problems in the report.
+    pub fn lenient(mut self, lenient: bool) -> ConversionOptions {
+        self.lenient = lenient;
+        self
+    }
+
+    pub fn mcn_placement(mut self, placement: Placement) -> ConversionOptions {
+        self.mcn_placement = placement;
+        self
+    }
+}
+
+/// What happened to the .img.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImgOutcome {
+    Written(PathBuf),
+    /// There was already an .img here, so it was left alone
+    AlreadyExists(PathBuf),
+    Skipped,
+}
+
+/// The LSD and SBI files a conversion used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SidecarReport {
+    pub lsd: Option<PathBuf>,
+    pub sbi: Option<PathBuf>,
+    /// The protection the data was recognised as, if it was recognised
+    /// with reasonable confidence
+    pub detected: Option<Candidate>,
+}
+
+impl SidecarReport {
+    /// Which kinds of file the data came from, such as "LSD and SBI".
+    pub fn description(&self) -> &'static str {
+        match (&self.lsd, &self.sbi) {
+            (Some(_), Some(_)) => "LSD and SBI",
+            (None, Some(_)) => "SBI",
+            _ => "LSD",
+        }
+    }
+}
+
+/// Everything a conversion did.
+#[derive(Clone, Debug)]
+pub struct ConversionReport {
+    pub ccd: PathBuf,
+    pub sub: PathBuf,
+    pub img: ImgOutcome,
+    pub sidecars: Option<SidecarReport>,
+    /// The protection the subchannel was generated with, on top of any
+    /// LSD or SBI data
+    pub protection: Option<DiscProtection>,
+    pub warnings: Vec<ConversionWarning>,
+}
+
+fn validate_files(files: &FileSource, cue_files: &[CueFile]) -> Result<(), ConvertError> {
+    for file in cue_files {
+        let filename = &file.name;
+        let path = Path::new(filename);
+        if file.file_type == FileType::Aiff {
+            return Err(ConvertError::UnsupportedFileType {
+                filename: filename.clone(),
+                file_type: file.file_type,
+            });
+        } else if let Some(kind) = CompressedFormat::from_path(path) {
+            if let Err(source) = DecodedAudio::from_source(Box::new(files.open(path)?), path, kind)
+            {
+                return Err(ConvertError::InvalidAudioFile {
+                    filename: filename.clone(),
+                    source,
+                });
+            }
+        } else if wave::is_wave(path) {
+            if let Err(source) = WaveData::read(&mut files.open(path)?) {
+                return Err(ConvertError::InvalidWaveFile {
+                    filename: filename.clone(),
+                    source,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a cuesheet and the files it references into a Disc, including
+/// its CATALOG, CD-TEXT and sessions.
+pub fn read_cuesheet(path: &Path) -> Result<Disc, ConvertError> {
+    let Some(root) = path.parent() else {
+        return Err(ConvertError::NoParent {
+            filename: path.display().to_string(),
+        });
+    };
+
+    let cue_sheet = std::fs::read_to_string(path)?;
+    disc_from_cuesheet(&cue_sheet, path, &FileSource::Directory(root.to_path_buf()))
+}
+
+// Parses a cuesheet and reads the files it references from `files`.
+// `path` is only used to say where the cuesheet is in errors.
+fn disc_from_cuesheet(
+    cue_sheet: &str,
+    path: &Path,
+    files: &FileSource,
+) -> Result<Disc, ConvertError> {
+    let cuesheet = Cuesheet::parse(cue_sheet).map_err(|source| ConvertError::InvalidCuesheet {
+        filename: path.display().to_string(),
+        source,
+    })?;
+    let catalog = cuesheet
+        .catalog
+        .as_deref()
+        .map(str::parse::<Mcn>)
+        .transpose()
+        .map_err(|source| ConvertError::InvalidCatalog { source })?;
+    let sessions = session::sessions_from_cuesheet(&cuesheet)
+        .map_err(|source| ConvertError::InvalidSessions { source })?;
+
+    for (i, track) in cuesheet.tracks.iter().enumerate() {
+        if let Some(isrc) = &track.isrc {
+            isrc.parse::<Isrc>()
+                .map_err(|source| ConvertError::InvalidIsrc {
+                    track: i + 1,
+                    source,
+                })?;
+        }
+    }
+
+    // We need the files to know how long the tracks are.
+    let filenames = cuesheet
+        .files
+        .iter()
+        .map(|file| file.name.clone())
+        .collect::<Vec<_>>();
+    let missing_files = filenames
+        .iter()
+        .filter(|f| !files.is_file(Path::new(f)))
+        .cloned()
+        .collect::<Vec<String>>();
+    if !missing_files.is_empty() {
+        return Err(ConvertError::MissingFiles { missing_files });
+    }
+
+    // We validate that the files are compatible. BIN/CUE can be
+    // a variety of different formats, including WAVE files and "cooked"
+    // tracks with no error correction metadata. CloneCD images are always
+    // raw, so cooked tracks are converted, WAVE files have their headers
+    // stripped and compressed audio is decoded while building the .img.
+    // That only works if the audio is CD-quality to begin with.
+    validate_files(files, &cuesheet.files)?;
+
+    let cd_text = cdtext::packs_from_cuesheet(&cuesheet, files)
+        .map_err(|source| ConvertError::InvalidCdText { source })?;
+    let mut disc = Disc::from_cuesheet(&cuesheet, files)?;
+    disc.cd_text = cd_text;
+    disc.catalog = catalog;
+    disc.sessions = sessions;
+
+    Ok(disc)
+}
+
+fn explicit_sidecar(path: &Option<PathBuf>) -> Result<Option<PathBuf>, ConvertError> {
+    match path {
+        Some(path) if !path.is_file() => Err(ConvertError::MissingSidecar {
+            filename: path.display().to_string(),
+        }),
+        path => Ok(path.clone()),
+    }
+}
+
+// Finds an LSD or SBI next to the cuesheet with the same name, ignoring case.
+fn find_sidecar(
+    files: &FileSource,
+    cue_name: &Path,
+    extension: &str,
+) -> Result<Option<PathBuf>, ConvertError> {
+    let Some(stem) = cue_name.file_stem() else {
+        return Ok(None);
+    };
+    let expected = Path::new(stem).with_extension(extension);
+    if files.is_file(&expected) {
+        return Ok(Some(expected));
+    }
+    for name in files.file_names()? {
+        let matches = |a: Option<&OsStr>, b: &OsStr| a.is_some_and(|a| a.eq_ignore_ascii_case(b));
+        if matches(name.file_stem(), stem) && matches(name.extension(), OsStr::new(extension)) {
+            return Ok(Some(name));
+        }
+    }
+
+    Ok(None)
+}
+
+// Picks which of the cuesheets in a ZIP archive to convert, returning its
+// path within the archive.
+fn find_archive_cuesheet(archive: &Path, chosen: Option<&str>) -> Result<String, ConvertError> {
+    let filename = archive.display().to_string();
+    let cuesheets = source::cuesheets_in_zip(archive).map_err(|error| match error {
+        ZipError::Io(error) => ConvertError::Io(error),
+        source => ConvertError::InvalidArchive {
+            filename: filename.clone(),
+            source,
+        },
+    })?;
+
+    let matching = match chosen {
+        Some(chosen) => {
+            let matching = cuesheets
+                .iter()
+                .filter(|name| *name == chosen || name.rsplit('/').next() == Some(chosen))
+                .cloned()
+                .collect::<Vec<_>>();
+            if matching.is_empty() {
+                return Err(ConvertError::MissingCuesheetInArchive {
+                    filename,
+                    cuesheet: chosen.to_string(),
+                    cuesheets,
+                });
+            }
+            matching
+        }
+        None => cuesheets,
+    };
+    match &matching[..] {
+        [] => Err(ConvertError::NoCuesheetInArchive { filename }),
+        [cuesheet] => Ok(cuesheet.clone()),
+        _ => Err(ConvertError::SeveralCuesheetsInArchive {
+            filename,
+            cuesheets: matching,
+        }),
+    }
+}
+
+fn write_img_file(disc: &Disc, files: &FileSource, path: &Path) -> io::Result<()> {
+    let mut out_file = BufWriter::new(File::create(path)?);
+    disc.write_img(files, &mut out_file)?;
+    out_file.flush()
+}
+
+/// Converts a single BIN/CUE into a CloneCD image.
+pub struct Converter {
+    options: ConversionOptions,
+}
+
+impl Converter {
+    pub fn new(options: ConversionOptions) -> Converter {
+        Converter { options }
+    }
+
+    pub fn convert(&self) -> Result<ConversionReport, ConvertError> {
+        let options = &self.options;
+        let cue_path = options.cue_path.as_path();
+        let filename = cue_path.display().to_string();
+        let Some(root) = cue_path.parent() else {
+            return Err(ConvertError::NoParent { filename });
+        };
+
+        // A cuesheet in an archive is read from there along with its files,
+        // but the CloneCD image still goes next to the archive.
+        let (files, basename, cue_sheet) = if source::is_zip(cue_path) {
+            let member = find_archive_cuesheet(cue_path, options.archive_cuesheet.as_deref())?;
+            let files = FileSource::zip_containing(cue_path, &member);
+            let basename = PathBuf::from(member.rsplit('/').next().unwrap_or(&member));
+            let cue_sheet = String::from_utf8(files.read(&basename)?)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            (files, basename, cue_sheet)
+        } else {
+            let Some(basename) = cue_path.file_name() else {
+                return Err(ConvertError::NoFilename { filename });
+            };
+            let files = FileSource::Directory(root.to_path_buf());
+            (
+                files,
+                PathBuf::from(basename),
+                std::fs::read_to_string(cue_path)?,
+            )
+        };
+        let output_dir = options.output_dir.as_deref().unwrap_or(root);
+        // Provides a pattern to build output filenames from
+        let output_stem = output_dir.join(&basename);
+
+        let mut disc = disc_from_cuesheet(&cue_sheet, &files.path(&basename), &files)?;
+        disc.mcn_placement = options.mcn_placement;
+
+        let mut warnings = vec![];
+        // Sidecars we're given are paths of their own rather than being
+        // relative to the cuesheet, so they're read from an empty root.
+        let (sidecar_files, lsd, sbi) = match &options.sidecars {
+            Sidecars::Find => (
+                files.clone(),
+                find_sidecar(&files, &basename, "lsd")?,
+                find_sidecar(&files, &basename, "sbi")?,
+            ),
+            Sidecars::Given { lsd, sbi } => (
+                FileSource::Directory(PathBuf::new()),
+                explicit_sidecar(lsd)?,
+                explicit_sidecar(sbi)?,
+            ),
+            Sidecars::Ignore => (files.clone(), None, None),
+        };
+
+        let mut preconstructed_q_subcodes = HashMap::new();
+        let mut sidecars = None;
+        let chosen_protection_type = if lsd.is_some() || sbi.is_some() {
+            let (q_subchannels, has_crcs) =
+                self.read_sidecars(&sidecar_files, &lsd, &sbi, &disc, &mut warnings)?;
+            let mut report = SidecarReport {
+                lsd: lsd.map(|lsd| sidecar_files.path(&lsd)),
+                sbi: sbi.map(|sbi| sidecar_files.path(&sbi)),
+                detected: None,
+            };
+            let protection =
+                self.protection_from_sidecar(&q_subchannels, &mut report, has_crcs, &mut warnings)?;
+            preconstructed_q_subcodes = q_subchannels;
+            sidecars = Some(report);
+            protection
+        } else {
+            self.protection_from_options(&mut warnings)
+        };
+
+        let sub_target = output_stem.with_extension("sub");
+        // Each sector only has 96 bytes of subchannel data, so this
+        // needs buffering to avoid a write for every sector.
+        let mut sub_write = BufWriter::new(File::create(&sub_target)?);
+
+        for sector in disc.sectors() {
+            sub_write.write_all(
+                &sector.generate_subchannel(&chosen_protection_type, &preconstructed_q_subcodes),
+            )?;
+        }
+        sub_write.flush()?;
+
+        let img_target = output_stem.with_extension("img");
+        let img = match options.img_policy {
+            ImgPolicy::Skip => ImgOutcome::Skipped,
+            ImgPolicy::KeepExisting if img_target.exists() => ImgOutcome::AlreadyExists(img_target),
+            ImgPolicy::KeepExisting | ImgPolicy::Overwrite => {
+                // The .img is written under another name until it's
+                // complete, so one left behind by a failed conversion
+                // isn't kept as if it were finished.
+                let partial = output_stem.with_extension("img.partial");
+                if let Err(error) = write_img_file(&disc, &files, &partial) {
+                    let _ = std::fs::remove_file(&partial);
+                    return Err(error.into());
+                }
+                std::fs::rename(&partial, &img_target)?;
+                ImgOutcome::Written(img_target)
+            }
+        };
+
+        // The .ccd goes last: its presence is what marks a conversion as
+        // done, for instance when converting a whole directory.
+        let ccd_target = output_stem.with_extension("ccd");
+        let mut ccd_write = File::create(&ccd_target)?;
+        disc.write_ccd(&mut ccd_write)?;
+
+        Ok(ConversionReport {
+            ccd: ccd_target,
+            sub: sub_target,
+            img,
+            sidecars,
+            protection: chosen_protection_type,
+            warnings,
+        })
+    }
+
+    // Reads the LSD and/or SBI, merging them if there are both. Also
+    // returns whether the data has the original CRCs; SBI files don't
+    // store the CRC, so they can't tell us whether it was bad.
+    fn read_sidecars(
+        &self,
+        files: &FileSource,
+        lsd: &Option<PathBuf>,
+        sbi: &Option<PathBuf>,
+        disc: &Disc,
+        warnings: &mut Vec<ConversionWarning>,
+    ) -> Result<(HashMap<i64, Vec<u8>>, bool), ConvertError> {
+        let mut read_lsd =
+            |name: &Path| self.read_sidecar(files, name, disc, sidecar::read_lsd, warnings);
+        let lsd_data = lsd.as_deref().map(&mut read_lsd).transpose()?;
+        let mut read_sbi =
+            |name: &Path| self.read_sidecar(files, name, disc, sidecar::read_sbi, warnings);
+        let sbi_data = sbi.as_deref().map(&mut read_sbi).transpose()?;
+
+        Ok(match (lsd_data, sbi_data) {
+            (Some(lsd_data), Some(sbi_data)) => {
+                // Entries only in the SBI have regenerated CRCs
+                let has_crcs = sbi_data.keys().all(|sector| lsd_data.contains_key(sector));
+                let (merged, conflicts) = sidecar::merge(lsd_data, sbi_data);
+                warnings.extend(
+                    conflicts
+                        .into_iter()
+                        .map(|sector| ConversionWarning::SidecarConflict { sector }),
+                );
+                (merged, has_crcs)
+            }
+            (Some(lsd_data), None) => (lsd_data, true),
+            (None, Some(sbi_data)) => (sbi_data, false),
+            (None, None) => (HashMap::new(), true),
+        })
+    }
+
+    // Reads an LSD or SBI, failing on damaged entries unless we were
+    // asked to be lenient.
+    fn read_sidecar(
+        &self,
+        files: &FileSource,
+        name: &Path,
+        disc: &Disc,
+        read: fn(&[u8], i64, Validation) -> Result<Sidecar, SidecarError>,
+        warnings: &mut Vec<ConversionWarning>,
+    ) -> Result<HashMap<i64, Vec<u8>>, ConvertError> {
+        let filename = files.path(name).display().to_string();
+        let validation = if self.options.lenient {
+            Validation::Lenient
+        } else {
+            Validation::Strict
+        };
+        // LSD and SBI files are very small, so it's simplest to read the whole
+        // thing in first.
+        let sidecar =
+            read(&files.read(name)?, disc.length_on_disc(), validation).map_err(|source| {
+                match source {
+                    // Being lenient won't help if it isn't an SBI at all
+                    SidecarError::InvalidSbiHeader => ConvertError::InvalidSbi,
+                    source => ConvertError::InvalidSidecar {
+                        filename: filename.clone(),
+                        source,
+                    },
+                }
+            })?;
+        warnings.extend(sidecar.warnings.into_iter().map(|warning| {
+            ConversionWarning::DamagedSidecar {
+                filename: filename.clone(),
+                warning,
+            }
+        }));
+
+        Ok(sidecar.q_subchannels)
+    }
+
+    // Works out which protection the sectors in an LSD or SBI belong to,
+    // checking them against the protection we were asked for if there is
+    // one. Returns the protection to generate the rest of the disc's
+    // subchannel with.
+    fn protection_from_sidecar(
+        &self,
+        q_subchannels: &HashMap<i64, Vec<u8>>,
+        report: &mut SidecarReport,
+        has_crcs: bool,
+        warnings: &mut Vec<ConversionWarning>,
+    ) -> Result<Option<DiscProtection>, ConvertError> {
+        let options = &self.options;
+        let sidecar = report.description();
+        let candidates = detect_protection(q_subchannels, has_crcs);
+
+        let detected = match &options.protection_type {
+            None => {
+                let Some(best) = candidates.first() else {
+                    return Ok(None);
+                };
+                if best.confidence == Confidence::Low {
+                    warnings.push(ConversionWarning::UncertainProtection {
+                        sidecar: sidecar.to_string(),
+                        candidate: best.clone(),
+                    });
+                    return Ok(None);
+                }
+                best
+            }
+            Some(protection_type) => {
+                let Some(detected) = candidates.iter().find(|c| {
+                    c.confidence > Confidence::Low && protection_type.includes(&c.protection)
+                }) else {
+                    return Err(self.protection_conflict(
+                        &candidates,
+                        sidecar,
+                        q_subchannels.len(),
+                    ));
+                };
+                detected
+            }
+        };
+        report.detected = Some(detected.clone());
+
+        match detected.protection {
+            // SecuROM's corrupted sectors are only known from the LSD/SBI,
+            // which is used as-is.
+            DiscProtection::SecuROMScheme1
+            | DiscProtection::SecuROMScheme2
+            | DiscProtection::SecuROMScheme3a
+            | DiscProtection::SecuROMScheme3b
+            | DiscProtection::SecuROMScheme3c
+            | DiscProtection::SecuROMScheme4 => Ok(None),
+            protection => Ok(Some(protection)),
+        }
+    }
+
+    fn protection_conflict(
+        &self,
+        candidates: &[Candidate],
+        sidecar: &str,
+        sectors: usize,
+    ) -> ConvertError {
+        let options = &self.options;
+        let chosen = match &options.protection_type {
+            Some(protection_type) => protection_type.name().to_string(),
+            None => unreachable!(),
+        };
+        // Explain why it isn't what the user asked for if it's close, or else
+        // what it looks like instead.
+        let closest = candidates
+            .iter()
+            .find(|c| {
+                options
+                    .protection_type
+                    .is_some_and(|t| t.includes(&c.protection))
+            })
+            .or(candidates.first());
+        let explanation = match closest {
+            Some(candidate) => format!(
+                "It looks like {}, with {} confidence: {}.",
+                candidate.protection, candidate.confidence, candidate.reason
+            ),
+            None => format!(
+                "Its {} sector(s) don't match any protection cue2ccd knows about.",
+                sectors
+            ),
+        };
+
+        ConvertError::ProtectionConflict {
+            sidecar: sidecar.to_string(),
+            chosen,
+            explanation,
+        }
+    }
+
+    // Without an LSD or SBI, the protection is generated entirely from
+    // what we were asked for.
+    fn protection_from_options(
+        &self,
+        warnings: &mut Vec<ConversionWarning>,
+    ) -> Option<DiscProtection> {
+        let options = &self.options;
+        match options.protection_type {
+            None => None,
+            Some(ProtectionType::DiscGuard) => Some(DiscProtection::DiscGuardScheme2),
+            Some(ProtectionType::LibCrypt) => {
+                // The key differs from game to game, and only the LSD/SBI can
+                // tell us what it is; without one, every bit is set unless
+                // we're told otherwise.
+                let key = options.libcrypt_key.unwrap_or_else(|| {
+                    warnings.push(ConversionWarning::MissingLibCryptKey);
+                    0xFFFF
+                });
+                Some(DiscProtection::LibCryptScheme2 { key })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, read_to_string, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::{ConversionOptions, ConvertError, Converter, ImgOutcome};
+    use crate::cuesheet::{CueErrorKind, FileType};
+
+    fn data_plus_audio() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("testdata")
+            .join("dataplusaudio")
+    }
+
+    #[test]
+    fn test_convert() {
+        let output_dir = std::env::temp_dir().join(format!("cdrom-convert-{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+
+        let options = ConversionOptions::new(data_plus_audio().join("bincue").join("disc.cue"))
+            .output_dir(&output_dir);
+        let report = Converter::new(options).convert().unwrap();
+
+        let real = data_plus_audio().join("ccd");
+        assert_eq!(report.ccd, output_dir.join("disc.ccd"));
+        assert_eq!(
+            read_to_string(&report.ccd).unwrap(),
+            read_to_string(real.join("disc.ccd")).unwrap()
+        );
+        assert_eq!(
+            read(&report.sub).unwrap(),
+            read(real.join("disc.sub")).unwrap()
+        );
+        assert_eq!(report.img, ImgOutcome::Written(output_dir.join("disc.img")));
+        assert_eq!(
+            read(output_dir.join("disc.img")).unwrap(),
+            read(real.join("disc.bin")).unwrap()
+        );
+        assert!(report.sidecars.is_none());
+        assert!(report.warnings.is_empty());
+
+        // An existing .img is left alone
+        let options = ConversionOptions::new(data_plus_audio().join("bincue").join("disc.cue"))
+            .output_dir(&output_dir);
+        let report = Converter::new(options).convert().unwrap();
+        assert_eq!(
+            report.img,
+            ImgOutcome::AlreadyExists(output_dir.join("disc.img"))
+        );
+
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_convert_motorola() {
+        let output_dir =
+            std::env::temp_dir().join(format!("cdrom-motorola-{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+
+        // The audio tracks in their own file, with each sample big-endian
+        let real = data_plus_audio().join("ccd");
+        let bin = read(real.join("disc.bin")).unwrap();
+        let (data, audio) = bin.split_at(316 * 2352);
+        let mut swapped = audio.to_vec();
+        for sample in swapped.chunks_exact_mut(2) {
+            sample.swap(0, 1);
+        }
+        std::fs::write(output_dir.join("data.bin"), data).unwrap();
+        std::fs::write(output_dir.join("audio.bin"), swapped).unwrap();
+        std::fs::write(
+            output_dir.join("disc.cue"),
+            "FILE \"data.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n\
+             FILE \"audio.bin\" MOTOROLA\n  TRACK 02 AUDIO\n    INDEX 00 00:00:00\n    INDEX 01 00:02:00\n\
+             \x20 TRACK 03 AUDIO\n    INDEX 00 00:03:00\n    INDEX 01 00:05:00\n",
+        )
+        .unwrap();
+
+        let options = ConversionOptions::new(output_dir.join("disc.cue"));
+        let report = Converter::new(options).convert().unwrap();
+        assert_eq!(
+            read_to_string(&report.ccd).unwrap(),
+            read_to_string(real.join("disc.ccd")).unwrap()
+        );
+        assert_eq!(read(output_dir.join("disc.img")).unwrap(), bin);
+
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_convert_zip() {
+        let output_dir = std::env::temp_dir().join(format!("cdrom-zip-{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+
+        // Two sets of the same disc, plus a macOS resource fork which
+        // shouldn't be mistaken for a third
+        let archive = output_dir.join("discs.zip");
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        let bincue = data_plus_audio().join("bincue");
+        for dir in ["first/", "second/", "__MACOSX/first/._"] {
+            for name in ["disc.cue", "disc.bin"] {
+                zip.start_file(format!("{}{}", dir, name), FileOptions::default())
+                    .unwrap();
+                zip.write_all(&read(bincue.join(name)).unwrap()).unwrap();
+            }
+        }
+        zip.finish().unwrap();
+
+        let options = ConversionOptions::new(&archive);
+        match Converter::new(options).convert() {
+            Err(ConvertError::SeveralCuesheetsInArchive { cuesheets, .. }) => {
+                assert_eq!(cuesheets, vec!["first/disc.cue", "second/disc.cue"])
+            }
+            result => panic!("expected several cuesheets, got {:?}", result),
+        }
+        let options = ConversionOptions::new(&archive).archive_cuesheet("third/disc.cue");
+        assert!(matches!(
+            Converter::new(options).convert(),
+            Err(ConvertError::MissingCuesheetInArchive { .. })
+        ));
+
+        let options = ConversionOptions::new(&archive).archive_cuesheet("second/disc.cue");
+        let report = Converter::new(options).convert().unwrap();
+
+        let real = data_plus_audio().join("ccd");
+        assert_eq!(report.ccd, output_dir.join("disc.ccd"));
+        assert_eq!(
+            read_to_string(&report.ccd).unwrap(),
+            read_to_string(real.join("disc.ccd")).unwrap()
+        );
+        assert_eq!(
+            read(&report.sub).unwrap(),
+            read(real.join("disc.sub")).unwrap()
+        );
+        assert_eq!(
+            read(output_dir.join("disc.img")).unwrap(),
+            read(real.join("disc.bin")).unwrap()
+        );
+
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_convert_errors() {
+        let options = ConversionOptions::new(data_plus_audio().join("bincue").join("missing.cue"));
+        assert!(matches!(
+            Converter::new(options).convert(),
+            Err(ConvertError::Io(_))
+        ));
+
+        // AIFF files would need decoding, which isn't supported
+        let output_dir = std::env::temp_dir().join(format!("cdrom-aiff-{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        std::fs::write(output_dir.join("disc.aiff"), [0; 2352]).unwrap();
+        std::fs::write(
+            output_dir.join("disc.cue"),
+            "FILE \"disc.aiff\" AIFF\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n",
+        )
+        .unwrap();
+        let options = ConversionOptions::new(output_dir.join("disc.cue"));
+        assert!(matches!(
+            Converter::new(options).convert(),
+            Err(ConvertError::UnsupportedFileType {
+                file_type: FileType::Aiff,
+                ..
+            })
+        ));
+        assert!(!output_dir.join("disc.ccd").exists());
+
+        // A pregap at the end of the previous track's file can't be
+        // laid out, so it's rejected rather than dropping the next file
+        for name in ["01.bin", "02.bin"] {
+            std::fs::write(output_dir.join(name), [0; 2352]).unwrap();
+        }
+        std::fs::write(
+            output_dir.join("disc.cue"),
+            "FILE \"01.bin\" BINARY\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\n    INDEX 00 00:00:01\nFILE \"02.bin\" BINARY\n    INDEX 01 00:00:00\n",
+        )
+        .unwrap();
+        let options = ConversionOptions::new(output_dir.join("disc.cue"));
+        match Converter::new(options).convert() {
+            Err(ConvertError::InvalidCuesheet { source, .. }) => {
+                assert_eq!(source.kind, CueErrorKind::TrackAcrossFiles { track: 2 })
+            }
+            result => panic!("expected an invalid cuesheet, got {:?}", result),
+        }
+        assert!(!output_dir.join("disc.ccd").exists());
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
@@ -0,0 +1,808 @@
+// Cuesheets are text files describing the layout of a disc image, one
+// command per line. The commands we understand are:
+//
+// FILE "name" type     - the file the following tracks are stored in. The
+//                        type is BINARY or MOTOROLA for raw little- and
+//                        big-endian data, or WAVE, MP3 or AIFF for audio.
+// TRACK nn mode        - starts a track, such as `TRACK 01 MODE1/2352`.
+// INDEX nn mm:ss:ff    - where an index starts within the current file;
+//                        INDEX 00 is the pregap and INDEX 01 the track proper.
+// PREGAP/POSTGAP time  - silence or empty sectors before or after the track
+//                        which aren't stored in the file.
+// FLAGS                - the track's DCP, 4CH, PRE and SCMS flags.
+// ISRC code            - the track's International Standard Recording Code.
+// CATALOG number       - the disc's media catalog number.
+// CDTEXTFILE "name"    - a file holding the disc's raw CD-TEXT packs.
+// TITLE, PERFORMER,    - CD-TEXT for the disc, or for the current track once
+// SONGWRITER, etc.       a TRACK has been seen.
+// REM name value       - comments; some tools use these for metadata such as
+//                        sessions or ReplayGain.
+//
+// Commands and keywords are case-insensitive, and quotes are only needed
+// around arguments containing spaces. Times are in minutes, seconds and
+// frames, with 75 frames in a second.
+//
+// Every parse error keeps the span of the text it's about, so that it can be
+// shown in context.
+
+use std::ops::Range;
+
+use miette::{Diagnostic, SourceSpan};
+use thiserror::Error;
+
+use crate::cdtext::{self, CdTextFields};
+use crate::{TrackFlags, TrackMode};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CueErrorKind {
+    #[error("unknown command {0}")]
+    UnknownCommand(String),
+
+    #[error("{command} is missing its {argument}")]
+    MissingArgument {
+        command: String,
+        argument: &'static str,
+    },
+
+    #[error("unexpected {0:?}")]
+    UnexpectedArgument(String),
+
+    #[error("unterminated quote")]
+    UnterminatedQuote,
+
+    #[error("{0:?} isn't a file type; expected BINARY, MOTOROLA, WAVE, MP3 or AIFF")]
+    InvalidFileType(String),
+
+    #[error("{0:?} isn't a track mode")]
+    InvalidTrackMode(String),
+
+    #[error("{0:?} isn't a valid track number")]
+    InvalidTrackNumber(String),
+
+    #[error("expected track {expected}, found track {found}")]
+    TrackOutOfOrder { expected: u8, found: u8 },
+
+    #[error("{0:?} isn't a valid index number")]
+    InvalidIndexNumber(String),
+
+    #[error("index {found} follows index {previous}")]
+    IndexOutOfOrder { previous: u8, found: u8 },
+
+    #[error("track {track} has indices in more than one FILE, which isn't supported")]
+    TrackAcrossFiles { track: u8 },
+
+    #[error("{0:?} isn't a valid time; expected mm:ss:ff")]
+    InvalidTime(String),
+
+    #[error("{0:?} isn't a track flag; expected DCP, 4CH, PRE or SCMS")]
+    InvalidFlag(String),
+
+    #[error("TRACK comes before any FILE")]
+    TrackWithoutFile,
+
+    #[error("{0} has to come after a TRACK")]
+    OutsideTrack(String),
+
+    #[error("{0} has to come before the first TRACK")]
+    InsideTrack(String),
+
+    #[error("track {0} doesn't have an INDEX 01")]
+    MissingIndex(u8),
+
+    #[error("the cuesheet doesn't contain any tracks")]
+    NoTracks,
+}
+
+/// A parse error, along with the cuesheet it was found in so that it can
+/// be shown as a diagnostic.
+#[derive(Error, Debug, Diagnostic)]
+#[error("{kind}")]
+pub struct CueError {
+    pub kind: CueErrorKind,
+    #[label]
+    pub span: SourceSpan,
+    #[source_code]
+    pub cue_sheet: String,
+}
+
+type ParseResult<T> = Result<T, (CueErrorKind, Range<usize>)>;
+
+/// How the data in a FILE is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    /// Raw little-endian data
+    Binary,
+    /// Raw big-endian data
+    Motorola,
+    Wave,
+    Mp3,
+    Aiff,
+}
+
+impl FileType {
+    fn from_cue_str(file_type: &str) -> Option<FileType> {
+        match file_type.to_ascii_uppercase().as_str() {
+            "BINARY" => Some(FileType::Binary),
+            "MOTOROLA" => Some(FileType::Motorola),
+            "WAVE" => Some(FileType::Wave),
+            "MP3" => Some(FileType::Mp3),
+            "AIFF" => Some(FileType::Aiff),
+            _ => None,
+        }
+    }
+
+    /// The name of the file type in a FILE command.
+    pub fn as_cue_str(&self) -> &'static str {
+        match self {
+            FileType::Binary => "BINARY",
+            FileType::Motorola => "MOTOROLA",
+            FileType::Wave => "WAVE",
+            FileType::Mp3 => "MP3",
+            FileType::Aiff => "AIFF",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CueFile {
+    pub name: String,
+    pub file_type: FileType,
+}
+
+/// A REM comment, such as `REM GENRE Rock`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rem {
+    /// The first word after REM
+    pub name: String,
+    /// Everything after the name
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CueTrack {
+    pub number: u8,
+    pub mode: TrackMode,
+    /// Which of the cuesheet's files the track is stored in
+    pub file: usize,
+    /// Index number and start sector of every index, relative to the start
+    /// of the file
+    pub indices: Vec<(u8, i64)>,
+    /// The length of the PREGAP, in sectors
+    pub pregap: Option<i64>,
+    /// The length of the POSTGAP, in sectors
+    pub postgap: Option<i64>,
+    pub flags: TrackFlags,
+    pub isrc: Option<String>,
+    pub cd_text: CdTextFields,
+    /// The REM comments between this TRACK and the next
+    pub rems: Vec<Rem>,
+    /// Where the TRACK command is in the cuesheet
+    pub span: Range<usize>,
+}
+
+impl CueTrack {
+    /// Where the given index starts, relative to the start of the file.
+    pub fn index(&self, number: u8) -> Option<i64> {
+        self.indices
+            .iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, start)| *start)
+    }
+
+    /// Where the track's data begins in its file; this is index 0 if
+    /// the track has a pregap stored in the file.
+    pub fn first_index(&self) -> i64 {
+        self.indices.first().map_or(0, |(_, start)| *start)
+    }
+
+    /// Where index 1 starts, relative to the start of the file. Parsed
+    /// tracks always have an index 1.
+    pub fn start(&self) -> i64 {
+        self.index(1).unwrap_or_else(|| self.first_index())
+    }
+}
+
+/// A parsed cuesheet.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cuesheet {
+    pub catalog: Option<String>,
+    pub cdtextfile: Option<String>,
+    /// CD-TEXT for the whole disc
+    pub cd_text: CdTextFields,
+    /// The REM comments before the first TRACK
+    pub rems: Vec<Rem>,
+    pub files: Vec<CueFile>,
+    pub tracks: Vec<CueTrack>,
+}
+
+struct Token<'a> {
+    // Without the quotes, if it was quoted
+    text: &'a str,
+    span: Range<usize>,
+}
+
+// Splits a line into words, treating anything in double quotes as a
+// single word. `offset` is where the line starts in the cuesheet.
+fn tokenize(line: &str, offset: usize) -> ParseResult<Vec<Token<'_>>> {
+    let mut tokens = vec![];
+    let mut position = 0;
+    loop {
+        let rest = &line[position..];
+        let trimmed = rest.trim_start();
+        position += rest.len() - trimmed.len();
+        if trimmed.is_empty() {
+            return Ok(tokens);
+        }
+
+        let (text, length) = if let Some(quoted) = trimmed.strip_prefix('"') {
+            let Some(end) = quoted.find('"') else {
+                return Err((
+                    CueErrorKind::UnterminatedQuote,
+                    offset + position..offset + line.trim_end().len(),
+                ));
+            };
+            (&quoted[..end], end + 2)
+        } else {
+            let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+            (&trimmed[..end], end)
+        };
+        tokens.push(Token {
+            text,
+            span: offset + position..offset + position + length,
+        });
+        position += length;
+    }
+}
+
+fn parse_time(token: &Token) -> ParseResult<i64> {
+    let invalid = || {
+        (
+            CueErrorKind::InvalidTime(token.text.to_string()),
+            token.span.clone(),
+        )
+    };
+    let parts = token
+        .text
+        .split(':')
+        .map(|part| {
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            part.parse::<i64>().map_err(|_| invalid())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts[..] {
+        [m, s, f] if s < 60 && f < 75 => Ok(m * 4500 + s * 75 + f),
+        _ => Err(invalid()),
+    }
+}
+
+fn set_text(fields: &mut CdTextFields, pack_type: u8, text: &str) {
+    match fields.text.iter_mut().find(|(t, _)| *t == pack_type) {
+        Some((_, existing)) => *existing = text.to_string(),
+        None => fields.text.push((pack_type, text.to_string())),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    cuesheet: Cuesheet,
+}
+
+impl<'a> Parser<'a> {
+    // The text of several words together, as written in the cuesheet, so
+    // that unquoted text containing spaces such as `TITLE Abbey Road` is
+    // kept as-is.
+    fn join(&self, tokens: &[Token<'a>]) -> &'a str {
+        match tokens {
+            [] => "",
+            [token] => token.text,
+            [first, .., last] => &self.text[first.span.start..last.span.end],
+        }
+    }
+
+    // Checks that a command has at least the arguments named in `names`
+    fn require(command: &Token, arguments: &[Token], names: &[&'static str]) -> ParseResult<()> {
+        if arguments.len() >= names.len() {
+            return Ok(());
+        }
+        let end = arguments.last().map_or(command.span.end, |t| t.span.end);
+        Err((
+            CueErrorKind::MissingArgument {
+                command: command.text.to_ascii_uppercase(),
+                argument: names[arguments.len()],
+            },
+            command.span.start..end,
+        ))
+    }
+
+    // Checks that a command has exactly the arguments named in `names`
+    fn arguments<'t, const N: usize>(
+        command: &Token,
+        arguments: &'t [Token<'a>],
+        names: [&'static str; N],
+    ) -> ParseResult<&'t [Token<'a>; N]> {
+        Self::require(command, arguments, &names)?;
+        if let Some(extra) = arguments.get(N) {
+            return Err((
+                CueErrorKind::UnexpectedArgument(extra.text.to_string()),
+                extra.span.clone(),
+            ));
+        }
+        Ok(arguments.try_into().expect("the length was just checked"))
+    }
+
+    fn current_track(&mut self, command: &Token) -> ParseResult<&mut CueTrack> {
+        self.cuesheet.tracks.last_mut().ok_or_else(|| {
+            (
+                CueErrorKind::OutsideTrack(command.text.to_ascii_uppercase()),
+                command.span.clone(),
+            )
+        })
+    }
+
+    fn before_tracks(&self, command: &Token) -> ParseResult<()> {
+        if self.cuesheet.tracks.is_empty() {
+            Ok(())
+        } else {
+            Err((
+                CueErrorKind::InsideTrack(command.text.to_ascii_uppercase()),
+                command.span.clone(),
+            ))
+        }
+    }
+
+    // Makes sure the last track has the index 1 everything else relies on
+    fn finish_track(&self) -> ParseResult<()> {
+        match self.cuesheet.tracks.last() {
+            Some(track) if track.index(1).is_none() => {
+                Err((CueErrorKind::MissingIndex(track.number), track.span.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn command(&mut self, command: &Token<'a>, arguments: &[Token<'a>]) -> ParseResult<()> {
+        let name = command.text.to_ascii_uppercase();
+        match name.as_str() {
+            "REM" => {
+                let rem = match arguments.split_first() {
+                    Some((name, value)) => Rem {
+                        name: name.text.to_string(),
+                        value: self.join(value).to_string(),
+                    },
+                    None => Rem {
+                        name: String::new(),
+                        value: String::new(),
+                    },
+                };
+                match self.cuesheet.tracks.last_mut() {
+                    Some(track) => track.rems.push(rem),
+                    None => self.cuesheet.rems.push(rem),
+                }
+            }
+            "CATALOG" => {
+                self.before_tracks(command)?;
+                let [catalog] = Self::arguments(command, arguments, ["catalog number"])?;
+                self.cuesheet.catalog = Some(catalog.text.to_string());
+            }
+            "CDTEXTFILE" => {
+                self.before_tracks(command)?;
+                Self::require(command, arguments, &["filename"])?;
+                self.cuesheet.cdtextfile = Some(self.join(arguments).to_string());
+            }
+            "FILE" => {
+                Self::require(command, arguments, &["filename", "file type"])?;
+                // Everything up to the type is the filename, even if it
+                // isn't quoted
+                let (file_type, name) = (
+                    &arguments[arguments.len() - 1],
+                    &arguments[..arguments.len() - 1],
+                );
+                let file_type = FileType::from_cue_str(file_type.text).ok_or_else(|| {
+                    (
+                        CueErrorKind::InvalidFileType(file_type.text.to_string()),
+                        file_type.span.clone(),
+                    )
+                })?;
+                self.cuesheet.files.push(CueFile {
+                    name: self.join(name).to_string(),
+                    file_type,
+                });
+            }
+            "TRACK" => {
+                let [number, mode] = Self::arguments(command, arguments, ["number", "mode"])?;
+                self.finish_track()?;
+                let Some(file) = self.cuesheet.files.len().checked_sub(1) else {
+                    return Err((CueErrorKind::TrackWithoutFile, command.span.clone()));
+                };
+                let found = match number.text.parse::<u8>() {
+                    Ok(n @ 1..=99) => n,
+                    _ => {
+                        return Err((
+                            CueErrorKind::InvalidTrackNumber(number.text.to_string()),
+                            number.span.clone(),
+                        ))
+                    }
+                };
+                // Tracks are numbered from 1 without gaps, so a track's
+                // number is also its position in the disc.
+                let expected = self.cuesheet.tracks.len() as u8 + 1;
+                if found != expected {
+                    return Err((
+                        CueErrorKind::TrackOutOfOrder { expected, found },
+                        number.span.clone(),
+                    ));
+                }
+                let track_mode = TrackMode::from_cue_str(mode.text).ok_or_else(|| {
+                    (
+                        CueErrorKind::InvalidTrackMode(mode.text.to_string()),
+                        mode.span.clone(),
+                    )
+                })?;
+
+                self.cuesheet.tracks.push(CueTrack {
+                    number: found,
+                    mode: track_mode,
+                    file,
+                    indices: vec![],
+                    pregap: None,
+                    postgap: None,
+                    flags: TrackFlags::default(),
+                    isrc: None,
+                    cd_text: CdTextFields::default(),
+                    rems: vec![],
+                    span: command.span.start..mode.span.end,
+                });
+            }
+            "INDEX" => {
+                let [number, time] = Self::arguments(command, arguments, ["number", "time"])?;
+                let start = parse_time(time)?;
+                let file = self.cuesheet.files.len().saturating_sub(1);
+                let track = self.current_track(command)?;
+                // A FILE between a TRACK and its first INDEX means the track
+                // is in that file. Once it has an index in one file, though,
+                // it can't carry on into the next.
+                if track.file != file {
+                    if !track.indices.is_empty() {
+                        return Err((
+                            CueErrorKind::TrackAcrossFiles {
+                                track: track.number,
+                            },
+                            command.span.start..time.span.end,
+                        ));
+                    }
+                    track.file = file;
+                }
+                let found = match number.text.parse::<u8>() {
+                    Ok(n @ 0..=99) => n,
+                    _ => {
+                        return Err((
+                            CueErrorKind::InvalidIndexNumber(number.text.to_string()),
+                            number.span.clone(),
+                        ))
+                    }
+                };
+                if let Some(&(previous, _)) = track.indices.last() {
+                    if found <= previous {
+                        return Err((
+                            CueErrorKind::IndexOutOfOrder { previous, found },
+                            number.span.clone(),
+                        ));
+                    }
+                }
+                track.indices.push((found, start));
+            }
+            "PREGAP" | "POSTGAP" => {
+                let [time] = Self::arguments(command, arguments, ["length"])?;
+                let length = parse_time(time)?;
+                let track = self.current_track(command)?;
+                if name == "PREGAP" {
+                    track.pregap = Some(length);
+                } else {
+                    track.postgap = Some(length);
+                }
+            }
+            "FLAGS" => {
+                Self::require(command, arguments, &["flags"])?;
+                let track = self.current_track(command)?;
+                for flag in arguments {
+                    if !track.flags.insert(flag.text) {
+                        return Err((
+                            CueErrorKind::InvalidFlag(flag.text.to_string()),
+                            flag.span.clone(),
+                        ));
+                    }
+                }
+            }
+            "ISRC" => {
+                let [isrc] = Self::arguments(command, arguments, ["code"])?;
+                self.current_track(command)?.isrc = Some(isrc.text.to_string());
+            }
+            // CD-TEXT fields we don't write, since they either can't be
+            // set from a cuesheet or are generated for us
+            "DISC_ID" | "GENRE" | "TOC_INFO1" | "TOC_INFO2" | "UPC_EAN" | "SIZE_INFO" => (),
+            _ => {
+                let Some(pack_type) = cdtext::pack_type(&name) else {
+                    return Err((
+                        CueErrorKind::UnknownCommand(command.text.to_string()),
+                        command.span.clone(),
+                    ));
+                };
+                Self::require(command, arguments, &["text"])?;
+                let text = self.join(arguments);
+                let fields = match self.cuesheet.tracks.last_mut() {
+                    Some(track) => &mut track.cd_text,
+                    None => &mut self.cuesheet.cd_text,
+                };
+                set_text(fields, pack_type, text);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse(mut self) -> ParseResult<Cuesheet> {
+        // Some tools start the file with a UTF-8 byte order mark
+        let mut offset = if self.text.starts_with('\u{FEFF}') {
+            '\u{FEFF}'.len_utf8()
+        } else {
+            0
+        };
+
+        for line in self.text[offset..].split_inclusive('\n') {
+            let tokens = tokenize(line, offset)?;
+            offset += line.len();
+            if let Some((command, arguments)) = tokens.split_first() {
+                self.command(command, arguments)?;
+            }
+        }
+
+        self.finish_track()?;
+        if self.cuesheet.tracks.is_empty() {
+            return Err((CueErrorKind::NoTracks, 0..0));
+        }
+
+        Ok(self.cuesheet)
+    }
+}
+
+impl Cuesheet {
+    pub fn parse(cue_sheet: &str) -> Result<Cuesheet, CueError> {
+        let parser = Parser {
+            text: cue_sheet,
+            cuesheet: Cuesheet::default(),
+        };
+        parser.parse().map_err(|(kind, span)| CueError {
+            kind,
+            span: span.into(),
+            cue_sheet: cue_sheet.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CueErrorKind, CueFile, Cuesheet, FileType, Rem};
+    use crate::{TrackFlags, TrackMode};
+
+    const CUE_SHEET: &str = "\u{FEFF}REM GENRE \"Video Game\"\r
+REM COMMENT ExactAudioCopy v1.0\r
+catalog 0724384260927\r
+TITLE \"Greatest Hits\"\r
+PERFORMER Some Band\r
+FILE \"My Disc (Track 1).bin\" BINARY\r
+  TRACK 01 MODE2/2352\r
+    INDEX 01 00:00:00\r
+FILE track2.wav WAVE\r
+  TRACK 02 AUDIO\r
+    TITLE \"Second\"\r
+    FLAGS DCP pre\r
+    ISRC USABC1234567\r
+    PREGAP 00:02:00\r
+    INDEX 01 00:00:00\r
+    INDEX 02 01:02:03\r
+    POSTGAP 00:01:00\r
+  REM SESSION 02\r
+";
+
+    #[test]
+    fn test_parse() {
+        let cuesheet = Cuesheet::parse(CUE_SHEET).unwrap();
+
+        assert_eq!(cuesheet.catalog.as_deref(), Some("0724384260927"));
+        assert_eq!(cuesheet.cdtextfile, None);
+        assert_eq!(
+            cuesheet.cd_text.text,
+            vec![
+                (0x80, "Greatest Hits".to_string()),
+                (0x81, "Some Band".to_string())
+            ]
+        );
+        assert_eq!(
+            cuesheet.rems,
+            vec![
+                Rem {
+                    name: "GENRE".to_string(),
+                    value: "Video Game".to_string(),
+                },
+                Rem {
+                    name: "COMMENT".to_string(),
+                    value: "ExactAudioCopy v1.0".to_string(),
+                }
+            ]
+        );
+        assert_eq!(
+            cuesheet.files,
+            vec![
+                CueFile {
+                    name: "My Disc (Track 1).bin".to_string(),
+                    file_type: FileType::Binary,
+                },
+                CueFile {
+                    name: "track2.wav".to_string(),
+                    file_type: FileType::Wave,
+                }
+            ]
+        );
+
+        let [first, second] = &cuesheet.tracks[..] else {
+            panic!("expected two tracks, found {:?}", cuesheet.tracks);
+        };
+        assert_eq!(first.mode, TrackMode::Mode2Raw);
+        assert_eq!(first.file, 0);
+        assert_eq!(first.indices, vec![(1, 0)]);
+        assert_eq!(&CUE_SHEET[first.span.clone()], "TRACK 01 MODE2/2352");
+
+        assert_eq!(second.number, 2);
+        assert_eq!(second.mode, TrackMode::Audio);
+        assert_eq!(second.file, 1);
+        assert_eq!(second.indices, vec![(1, 0), (2, 4653)]);
+        assert_eq!(second.start(), 0);
+        assert_eq!(second.pregap, Some(150));
+        assert_eq!(second.postgap, Some(75));
+        assert_eq!(
+            second.flags,
+            TrackFlags {
+                copy_permitted: true,
+                pre_emphasis: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(second.isrc.as_deref(), Some("USABC1234567"));
+        assert_eq!(second.cd_text.text, vec![(0x80, "Second".to_string())]);
+        assert_eq!(
+            second.rems,
+            vec![Rem {
+                name: "SESSION".to_string(),
+                value: "02".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_file_between_track_and_index() {
+        // The track belongs to the file its first INDEX is in
+        let cuesheet = Cuesheet::parse(
+            "FILE \"a.bin\" BINARY\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\nFILE \"b.bin\" BINARY\n    INDEX 01 00:00:00\n",
+        )
+        .unwrap();
+        assert_eq!(cuesheet.tracks[0].file, 0);
+        assert_eq!(cuesheet.tracks[1].file, 1);
+    }
+
+    // Checks the error for a cuesheet and the text it points at
+    fn error(cue_sheet: &str) -> (CueErrorKind, &str) {
+        let error = Cuesheet::parse(cue_sheet).unwrap_err();
+        let span = error.span.offset()..error.span.offset() + error.span.len();
+        (error.kind, &cue_sheet[span])
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let valid = "FILE \"a.bin\" BINARY\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n";
+        assert!(Cuesheet::parse(valid).is_ok());
+
+        assert_eq!(
+            error(&valid.replace("INDEX 01 00:00:00", "INDEX 01 00:60:00")),
+            (
+                CueErrorKind::InvalidTime("00:60:00".to_string()),
+                "00:60:00"
+            )
+        );
+        assert_eq!(
+            error(&valid.replace("AUDIO", "MODE3/2352")),
+            (
+                CueErrorKind::InvalidTrackMode("MODE3/2352".to_string()),
+                "MODE3/2352"
+            )
+        );
+        assert_eq!(
+            error(&valid.replace("BINARY", "OGG")),
+            (CueErrorKind::InvalidFileType("OGG".to_string()), "OGG")
+        );
+        assert_eq!(
+            error(&valid.replace("\"a.bin\"", "\"a.bin")),
+            (CueErrorKind::UnterminatedQuote, "\"a.bin BINARY")
+        );
+        assert_eq!(
+            error(&valid.replace("INDEX 01", "INDEX 00")),
+            (CueErrorKind::MissingIndex(1), "TRACK 01 AUDIO")
+        );
+        assert_eq!(
+            error(&valid.replace("    INDEX", "    FLAGS DCP NOPE\n    INDEX")),
+            (CueErrorKind::InvalidFlag("NOPE".to_string()), "NOPE")
+        );
+        assert_eq!(
+            error(&valid.replace("TRACK 01 AUDIO", "TRACK 01")),
+            (
+                CueErrorKind::MissingArgument {
+                    command: "TRACK".to_string(),
+                    argument: "mode",
+                },
+                "TRACK 01"
+            )
+        );
+        assert_eq!(
+            error(&valid.replace("00:00:00", "00:00:00 00:02:00")),
+            (
+                CueErrorKind::UnexpectedArgument("00:02:00".to_string()),
+                "00:02:00"
+            )
+        );
+        assert_eq!(
+            error(&format!("ISRC USABC1234567\n{}", valid)),
+            (CueErrorKind::OutsideTrack("ISRC".to_string()), "ISRC")
+        );
+        assert_eq!(
+            error(&format!("{}CATALOG 0724384260927\n", valid)),
+            (CueErrorKind::InsideTrack("CATALOG".to_string()), "CATALOG")
+        );
+        assert_eq!(
+            error(&format!("{}  TRACK 01 AUDIO\n", valid)),
+            (
+                CueErrorKind::TrackOutOfOrder {
+                    expected: 2,
+                    found: 1,
+                },
+                "01"
+            )
+        );
+        assert_eq!(
+            error(&valid.replace("TRACK 01", "TRACK 02")),
+            (
+                CueErrorKind::TrackOutOfOrder {
+                    expected: 1,
+                    found: 2,
+                },
+                "02"
+            )
+        );
+        assert_eq!(
+            error(&format!("{}  TRACK 03 AUDIO\n", valid)),
+            (
+                CueErrorKind::TrackOutOfOrder {
+                    expected: 2,
+                    found: 3,
+                },
+                "03"
+            )
+        );
+        assert_eq!(
+            error(&format!(
+                "{}  TRACK 02 AUDIO\n    INDEX 00 00:10:00\nFILE \"b.bin\" BINARY\n    INDEX 01 00:00:00\n",
+                valid
+            )),
+            (
+                CueErrorKind::TrackAcrossFiles { track: 2 },
+                "INDEX 01 00:00:00"
+            )
+        );
+        assert_eq!(
+            error(&valid.replace("INDEX", "INDX")),
+            (CueErrorKind::UnknownCommand("INDX".to_string()), "INDX")
+        );
+        assert_eq!(error("REM nothing here\n"), (CueErrorKind::NoTracks, ""));
+    }
+}
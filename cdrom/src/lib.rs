@@ -1,14 +1,37 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, Write};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::path::Path;
 
 pub use cdrom_crc::{crc16, CRC16_INITIAL_CRC};
-pub use cue;
-use cue::cd::CD;
-use cue::track;
 
-fn lba_to_msf(lba: i64) -> (i64, i64, i64) {
+pub mod audio;
+pub mod ccd;
+pub mod cdtext;
+pub mod convert;
+pub mod cuesheet;
+pub mod libcrypt;
+pub mod protection;
+pub mod qmode;
+pub mod raw;
+pub mod securom;
+pub mod session;
+pub mod sidecar;
+pub mod source;
+pub mod verify;
+pub mod wave;
+
+use audio::{CompressedFormat, DecodedAudio};
+use ccd::{Ccd, CcdError};
+use cuesheet::{CueFile, CueTrack, Cuesheet, FileType};
+use qmode::{Isrc, Mcn, Placement};
+use raw::RAW_SECTOR_SIZE;
+use session::Session;
+use source::FileSource;
+use wave::WaveData;
+
+pub fn lba_to_msf(lba: i64) -> (i64, i64, i64) {
     (lba / 4500, (lba / 75) % 60, lba % 75)
 }
 
@@ -25,20 +48,190 @@ pub fn amsf_to_asec(m: i64, s: i64, f: i64) -> i64 {
 pub struct Disc {
     pub tracks: Vec<Track>,
     pub sector_count: i64,
+    /// Raw CD-TEXT packs, written to the .ccd's [CDText] section
+    pub cd_text: Vec<cdtext::Pack>,
+    /// The media catalog number, written into mode 2 Q subchannel frames
+    pub catalog: Option<Mcn>,
+    /// Which sectors get mode 2 frames, if there's a catalog number
+    pub mcn_placement: Placement,
+    /// Every session after the first; empty for single-session discs
+    pub sessions: Vec<Session>,
 }
 
 impl Disc {
     pub fn sectors(&self) -> SectorIterator<'_> {
         SectorIterator {
             current: 0,
+            track: 0,
+            index: 0,
             disc: self,
         }
     }
 
+    /// The number of the session a track is in.
+    pub fn session_number(&self, track: &Track) -> u8 {
+        1 + self
+            .sessions
+            .iter()
+            .filter(|session| session.first_track <= track.number)
+            .count() as u8
+    }
+
+    // Where a session begins in the image
+    fn session_start(&self, session: &Session) -> i64 {
+        self.tracks
+            .iter()
+            .find(|track| track.number == session.first_track)
+            .map(|track| track.indices[0].start)
+            .unwrap_or(self.sector_count)
+    }
+
+    // How far the given session is from where it is in the image,
+    // not counting the 150 sectors before the first track.
+    fn session_offset(&self, number: u8) -> i64 {
+        self.sessions
+            .iter()
+            .filter(|session| session.number <= number)
+            .map(|session| session.gap)
+            .sum()
+    }
+
+    /// Converts a sector number in the image into an absolute position on
+    /// the disc, as stored in the Q subchannel and in sector headers. This
+    /// counts the 150 sectors before the first track as well as the areas
+    /// between sessions, neither of which are in the image.
+    pub fn absolute_sector(&self, sector: i64) -> i64 {
+        let gaps = self
+            .sessions
+            .iter()
+            .filter(|session| self.session_start(session) <= sector)
+            .map(|session| session.gap)
+            .sum::<i64>();
+
+        sector + 150 + gaps
+    }
+
+    /// The number of sectors from the start of the first track to the
+    /// last session's lead-out, including the areas between sessions.
+    pub fn length_on_disc(&self) -> i64 {
+        self.sector_count + self.session_offset(u8::MAX)
+    }
+
     pub fn write_ccd(&self, writer: &mut File) -> io::Result<()> {
         write!(writer, "{}", self.generate_ccd())
     }
 
+    /// Writes the disc's main channel data in the raw format used by
+    /// CloneCD .img files. Tracks that are already raw are copied as-is,
+    /// while cooked tracks are rebuilt into full 2352-byte sectors.
+    /// The files the cuesheet referenced are read from `files`.
+    pub fn write_img<W: Write>(&self, files: &FileSource, writer: &mut W) -> io::Result<()> {
+        // Tracks are contiguous, so each track picks up wherever the
+        // previous one ended; this also covers the track's pregap.
+        // Tracks sharing a file are always next to each other, so each
+        // file can be read straight through without seeking, which
+        // matters for compressed audio.
+        let mut written = 0;
+        for file_tracks in self.tracks.chunk_by(|a, b| a.filename == b.filename) {
+            let mut file = BufReader::new(open_source(
+                files,
+                &file_tracks[0].filename,
+                file_tracks[0].file_type,
+            )?);
+
+            for track in file_tracks {
+                let end = track.start + track.length;
+                // Gaps from PREGAP and POSTGAP commands aren't in the
+                // file, so their sectors are generated instead.
+                let data_start = written + track.virtual_pregap;
+                let data_end = end - track.virtual_postgap;
+                self.write_empty_sectors(track.mode, written..data_start, writer)?;
+
+                let sector_size = track.mode.sector_size();
+                if track.mode == TrackMode::Audio && track.file_type == FileType::Motorola {
+                    // MOTOROLA files store each 16-bit sample big-endian,
+                    // but CloneCD images are little-endian like BINARY.
+                    let mut data = [0; RAW_SECTOR_SIZE];
+                    for _ in data_start..data_end {
+                        file.read_exact(&mut data)?;
+                        for sample in data.chunks_exact_mut(2) {
+                            sample.swap(0, 1);
+                        }
+                        writer.write_all(&data)?;
+                    }
+                } else if sector_size == RAW_SECTOR_SIZE {
+                    let length = (data_end - data_start) as u64 * RAW_SECTOR_SIZE as u64;
+                    io::copy(&mut file.by_ref().take(length), writer)?;
+                } else {
+                    let mut data = vec![0; sector_size];
+                    for sector in data_start..data_end {
+                        file.read_exact(&mut data)?;
+                        // The header stores the absolute position, counting
+                        // the 150 lead-in sectors.
+                        let absolute_sector = self.absolute_sector(sector);
+                        let raw = match track.mode {
+                            TrackMode::Mode1 => raw::mode1_sector(absolute_sector, &data),
+                            TrackMode::Mode2 => raw::mode2_sector(absolute_sector, &data),
+                            TrackMode::Mode2Form1 => raw::mode2_form1_sector(
+                                absolute_sector,
+                                &raw::FORM1_SUBHEADER,
+                                &data,
+                            ),
+                            TrackMode::Mode2Form2 => raw::mode2_form2_sector(
+                                absolute_sector,
+                                &raw::FORM2_SUBHEADER,
+                                &data,
+                            ),
+                            TrackMode::Mode2FormMix => {
+                                raw::mode2_mixed_sector(absolute_sector, &data)
+                            }
+                            // Raw tracks were already copied above
+                            TrackMode::Audio | TrackMode::Mode1Raw | TrackMode::Mode2Raw => {
+                                unreachable!()
+                            }
+                        };
+                        writer.write_all(&raw)?;
+                    }
+                }
+
+                self.write_empty_sectors(track.mode, data_end..end, writer)?;
+                written = end;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes sectors with nothing in them: silence for audio tracks, and
+    // zeroed user data for data tracks. Real discs use form 2 sectors
+    // for the gaps in mode 2 tracks.
+    fn write_empty_sectors<W: Write>(
+        &self,
+        mode: TrackMode,
+        sectors: Range<i64>,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        for sector in sectors {
+            let absolute_sector = self.absolute_sector(sector);
+            let raw = match mode {
+                TrackMode::Audio => [0; RAW_SECTOR_SIZE],
+                TrackMode::Mode1 | TrackMode::Mode1Raw => {
+                    raw::mode1_sector(absolute_sector, &[0; 2048])
+                }
+                TrackMode::Mode2
+                | TrackMode::Mode2Form1
+                | TrackMode::Mode2Form2
+                | TrackMode::Mode2FormMix
+                | TrackMode::Mode2Raw => {
+                    raw::mode2_form2_sector(absolute_sector, &raw::FORM2_SUBHEADER, &[0; 2324])
+                }
+            };
+            writer.write_all(&raw)?;
+        }
+
+        Ok(())
+    }
+
     pub fn generate_ccd(&self) -> String {
         let mut result = String::new();
 
@@ -51,58 +244,91 @@ impl Disc {
         result.push_str("Version=3\n\n");
 
         result.push_str("[Disc]\n");
-        // We always write out exactly 3 TOC entries more than the number of tracks.
-        // That accounts for extra TOC entries such as the leadout.
-        result.push_str(format!("TocEntries={}\n", self.tracks.len() + 3).as_str());
-        // Multisession cuesheets are rare, we're pretending they don't exist
-        result.push_str("Sessions=1\n");
+        // Every session has 3 TOC entries on top of its tracks, for the
+        // first track, last track and leadout. Multisession discs also have
+        // a B0 entry in every session but the last, pointing to the next
+        // session, and a C0 entry in the first session.
+        let session_count = self.sessions.len() + 1;
+        let mut toc_entries = self.tracks.len() + session_count * 3;
+        if session_count > 1 {
+            toc_entries += session_count;
+        }
+        result.push_str(format!("TocEntries={}\n", toc_entries).as_str());
+        result.push_str(format!("Sessions={}\n", session_count).as_str());
         result.push_str("DataTracksScrambled=0\n");
-        // CD-TEXT not yet supported
-        result.push_str("CDTextLength=0\n\n");
-
-        // To match other tools, we write track 1 and the final track before
-        // going back to write the other tracks.
-        let first_track = &self.tracks[0];
-        let last_track = if self.tracks.len() > 1 {
-            &self.tracks[self.tracks.len() - 1]
-        } else {
-            first_track
-        };
+        result.push_str(
+            format!("CDTextLength={}\n", self.cd_text.len() * cdtext::PACK_SIZE).as_str(),
+        );
+        if let Some(catalog) = &self.catalog {
+            result.push_str(format!("CATALOG={}\n", catalog).as_str());
+        }
+        result.push('\n');
+
+        if !self.cd_text.is_empty() {
+            result.push_str("[CDText]\n");
+            result.push_str(format!("Entries={}\n", self.cd_text.len()).as_str());
+            // CloneCD leaves out each pack's CRC, but still counts it in
+            // CDTextLength
+            for (i, pack) in self.cd_text.iter().enumerate() {
+                let bytes = pack[..16]
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                result.push_str(format!("Entry {}={}\n", i, bytes).as_str());
+            }
+            result.push('\n');
+        }
 
-        result.push_str("[Session 1]\n");
-        // Appears to be the type of the first track;
-        // even in a mixed-mode disc, this is only specified once.
-        // Is it possible for this to differ from the type of the first track? Unclear.
-        result.push_str(format!("PreGapMode={}\n", first_track.mode.as_u8()).as_str());
-        // Appears to be subchannel for pregap according to Aaru:
-        // https://github.com/aaru-dps/Aaru/blob/5410ae5e74f2177887cd1e0e1866d8d55cf244d9/Aaru.Images/CloneCD/Constants.cs#L50
-        // Unclear what the "correct" value is, but safe to hardcode.
-        result.push_str("PreGapSubC=0\n\n");
+        let session_tracks = self
+            .tracks
+            .chunk_by(|a, b| self.session_number(a) == self.session_number(b))
+            .collect::<Vec<_>>();
+
+        for (i, tracks) in session_tracks.iter().enumerate() {
+            result.push_str(format!("[Session {}]\n", i + 1).as_str());
+            // Appears to be the type of the session's first track;
+            // even in a mixed-mode disc, this is only specified once.
+            // Is it possible for this to differ from the type of the first track? Unclear.
+            result.push_str(format!("PreGapMode={}\n", tracks[0].mode.as_u8()).as_str());
+            // Appears to be subchannel for pregap according to Aaru:
+            // https://github.com/aaru-dps/Aaru/blob/5410ae5e74f2177887cd1e0e1866d8d55cf244d9/Aaru.Images/CloneCD/Constants.cs#L50
+            // Unclear what the "correct" value is, but safe to hardcode.
+            result.push_str("PreGapSubC=0\n\n");
+        }
 
         let mut entry = 0;
 
-        result.push_str(
-            self.generate_track(entry, Pointer::FirstTrack, first_track)
-                .as_str(),
-        );
-        entry += 1;
-        result.push_str(
-            self.generate_track(entry, Pointer::LastTrack, last_track)
-                .as_str(),
-        );
-        entry += 1;
-        result.push_str(
-            self.generate_track(entry, Pointer::LeadOut, last_track)
-                .as_str(),
-        );
-        entry += 1;
+        for (i, tracks) in session_tracks.iter().enumerate() {
+            // To match other tools, we write the session's first and final
+            // tracks before going back to write the other tracks.
+            let first_track = &tracks[0];
+            let last_track = &tracks[tracks.len() - 1];
 
-        for track in &self.tracks {
-            result.push_str(
-                self.generate_track(entry, Pointer::Track(track.number), track)
-                    .as_str(),
-            );
-            entry += 1;
+            let mut pointers = vec![Pointer::FirstTrack, Pointer::LastTrack, Pointer::LeadOut];
+            if i + 1 < session_tracks.len() {
+                pointers.push(Pointer::NextProgramArea);
+            }
+            if i == 0 && session_tracks.len() > 1 {
+                pointers.push(Pointer::FirstLeadIn);
+            }
+            for pointer in pointers {
+                let track = if let Pointer::FirstTrack = pointer {
+                    first_track
+                } else {
+                    last_track
+                };
+                result.push_str(self.generate_track(entry, pointer, track).as_str());
+                entry += 1;
+            }
+
+            for track in tracks.iter() {
+                result.push_str(
+                    self.generate_track(entry, Pointer::Track(track.number), track)
+                        .as_str(),
+                );
+                entry += 1;
+            }
         }
 
         // Next, we want to handle writing out the track index.
@@ -120,14 +346,19 @@ impl Disc {
         // in a plaintext INI format.
         // For some more information keys and their values, see
         // https://psx-spx.consoledev.net/cdromdrive/
+        let session = self.session_number(track);
         result.push_str(format!("[Entry {}]\n", entry).as_str());
-        result.push_str("Session=1\n");
+        result.push_str(format!("Session={}\n", session).as_str());
         // Pointer is either a track number from 1 to 99, *or* it's a control
         // code. Valid control codes according to the spec are:
         // A0 - P-MIN field indicates the first information track, and P-SEC/P-FRAC are zero
         // A1 - P-MIN field indicates the last information track, and P-SEC/P-FRAC are zero
         // A2 - P-MIN field indicates the start of the leadout, and P-SEC/P-FRAC are zero
         // For more detail, see section 22.3.4.2 of ECMA-130.
+        // Multisession discs also use these mode 5 pointers from the Orange Book:
+        // B0 - A-MIN/SEC/FRAC indicate the start of the next session's program area,
+        //      and P-MIN/SEC/FRAC the latest the last leadout could start
+        // C0 - P-MIN/SEC/FRAC indicate the start of the first leadin
         result.push_str(format!("Point=0x{:02x}\n", pointer.as_u8()).as_str());
 
         // Next, based on that value, we need to determine how to set M/S/F.
@@ -136,6 +367,9 @@ impl Disc {
         let m;
         let s;
         let f;
+        // Only used by B0 and C0
+        let mut alba = -150;
+        let mut zero = 0;
         match pointer {
             Pointer::FirstTrack | Pointer::LastTrack => {
                 lba = track.number as i64 * 4500 - 150;
@@ -144,42 +378,78 @@ impl Disc {
                 f = 0;
             }
             Pointer::LeadOut => {
-                lba = self.sector_count;
+                // The leadout starts where the session's last track ends;
+                // the next session, if there is one, starts right after it
+                // in the image.
+                let end = self
+                    .sessions
+                    .iter()
+                    .find(|next| next.number == session + 1)
+                    .map(|next| self.session_start(next))
+                    .unwrap_or(self.sector_count);
+                lba = end + self.session_offset(session);
                 // M/S/F is absolute, counting the 150 lead-in sectors,
                 // while the LBA is relative to the start of disc content.
                 (m, s, f) = lba_to_msf(lba + 150);
             }
+            Pointer::NextProgramArea => {
+                // The next program area starts with the first track's pregap,
+                // which isn't in the image unless it's listed as index 0.
+                let next = self
+                    .sessions
+                    .iter()
+                    .find(|next| next.number == session + 1)
+                    .unwrap();
+                let next_track = self
+                    .tracks
+                    .iter()
+                    .find(|track| track.number == next.first_track)
+                    .unwrap();
+                alba = self.absolute_sector(next_track.indices[0].start) - 150;
+                if next_track.indices[0].number != 0 {
+                    alba -= session::PREGAP;
+                }
+                // Two pointers in the first session, where there's also C0
+                zero = if session == 1 { 2 } else { 1 };
+                (m, s, f) = session::MAX_LEAD_OUT;
+                lba = m * 4500 + s * 75 + f - 150;
+            }
+            Pointer::FirstLeadIn => {
+                (m, s, f) = session::FIRST_LEAD_IN;
+                // Times from 90:00:00 onward are negative LBAs,
+                // counting back from the start of the disc.
+                lba = m * 4500 + s * 75 + f - 450150;
+            }
             _ => {
-                lba = track.start;
-                (m, s, f) = lba_to_msf(track.start + 150);
+                lba = track.start + self.session_offset(session);
+                (m, s, f) = lba_to_msf(lba + 150);
             }
         }
 
-        result.push_str("ADR=0x01\n");
-        // Control field. This is a 4-bit value defining the track type.
-        // There are more settings, but we only set these two.
-        // See section 22.3.1 of ECMA-130.
-        // TODO: Ensure this control code is correct for leadin and leadout.
-        // One real disc had 0 for the leadin when the first track was data,
-        // while other discs use 4. 4 is *probably* safe.
-        let control = if let TrackMode::Audio = track.mode {
-            // Audio track, all bits 0
-            0
-        } else {
-            // Data with copy flag set - 0100
-            4
+        let adr = match pointer {
+            Pointer::NextProgramArea | Pointer::FirstLeadIn => 5,
+            _ => 1,
         };
-        result.push_str(format!("Control=0x{:02x}\n", control).as_str());
+        result.push_str(format!("ADR=0x{:02x}\n", adr).as_str());
+        // Control field. This is a 4-bit value describing the track's type
+        // and flags; see section 22.3.1 of ECMA-130.
+        // Pointers that aren't tracks take the control field of the track
+        // they describe: A0 uses the session's first track, while A1, the
+        // leadout and the multisession pointers use its last track. This is
+        // why some discs have an audio control field for the leadout even
+        // though they start with a data track.
+        result.push_str(format!("Control=0x{:02x}\n", track.control()).as_str());
         // Yes, this is hardcodable despite what it looks like
         result.push_str("TrackNo=0\n");
         // Despite the A-MIN/SEC/FRAC values in the subchannel always containing
-        // an absolute timestamp, here they're always zeroed out.
-        result.push_str("AMin=0\n");
-        result.push_str("ASec=0\n");
-        result.push_str("AFrame=0\n");
+        // an absolute timestamp, here they're always zeroed out, except in B0.
+        let (amin, asec, aframe) = lba_to_msf(alba + 150);
+        result.push_str(format!("AMin={}\n", amin).as_str());
+        result.push_str(format!("ASec={}\n", asec).as_str());
+        result.push_str(format!("AFrame={}\n", aframe).as_str());
         // Should probably be calculated based on the pregap
-        result.push_str("ALBA=-150\n");
-        result.push_str("Zero=0\n");
+        result.push_str(format!("ALBA={}\n", alba).as_str());
+        result.push_str(format!("Zero={}\n", zero).as_str());
         // These three next values are the absolute MIN/SEC/FRAC
         result.push_str(format!("PMin={}\n", m).as_str());
         result.push_str(format!("PSec={}\n", s).as_str());
@@ -194,9 +464,93 @@ impl Disc {
 
         result.push_str(format!("[TRACK {}]\n", track.number).as_str());
         result.push_str(format!("MODE={}\n", track.mode.as_u8()).as_str());
+        if let Some(isrc) = &track.isrc {
+            result.push_str(format!("ISRC={}\n", isrc).as_str());
+        }
+        // Not part of CloneCD's format, but DiscImageCreator writes it,
+        // and it's the only way to keep SCMS.
+        let flags = track.flags.names();
+        if !flags.is_empty() {
+            result.push_str(format!("FLAGS= {}\n", flags.join(" ")).as_str());
+        }
 
+        // Index positions are on the disc, like the TOC's
+        let offset = self.session_offset(self.session_number(track));
         for index in &track.indices {
-            result.push_str(format!("INDEX {}={}\n", index.number, index.start).as_str());
+            result.push_str(format!("INDEX {}={}\n", index.number, index.start + offset).as_str());
+        }
+
+        result
+    }
+
+    pub fn write_cue(&self, writer: &mut File) -> io::Result<()> {
+        write!(writer, "{}", self.generate_cue())
+    }
+
+    /// Generates a cuesheet describing the disc, using each track's
+    /// `filename` for its FILE command.
+    pub fn generate_cue(&self) -> String {
+        let mut result = String::new();
+
+        if let Some(catalog) = &self.catalog {
+            result.push_str(format!("CATALOG {}\n", catalog).as_str());
+        }
+        if !self.sessions.is_empty() {
+            result.push_str("REM SESSION 01\n");
+        }
+
+        // Index positions in a cuesheet are relative to the start of the
+        // file they're in, so we need to keep track of where each file
+        // begins. As in write_img, tracks sharing a file are contiguous.
+        let mut written = 0;
+        for file_tracks in self.tracks.chunk_by(|a, b| a.filename == b.filename) {
+            let file_start = written;
+            let path = Path::new(&file_tracks[0].filename);
+            let file_type = if file_tracks[0].file_type == FileType::Motorola {
+                "MOTOROLA"
+            } else if wave::is_wave(path) || CompressedFormat::from_path(path).is_some() {
+                "WAVE"
+            } else {
+                "BINARY"
+            };
+            result
+                .push_str(format!("FILE \"{}\" {}\n", file_tracks[0].filename, file_type).as_str());
+
+            for track in file_tracks {
+                if let Some(session) = self
+                    .sessions
+                    .iter()
+                    .find(|session| session.first_track == track.number)
+                {
+                    // Describe the gap in a way sessions_from_cuesheet
+                    // will read back, assuming the usual lead-in and pregap.
+                    let mut lead_out = session.gap - session::LEAD_IN;
+                    if track.indices[0].number != 0 {
+                        lead_out -= session::PREGAP;
+                    }
+                    let (m, s, f) = lba_to_msf(lead_out);
+                    result.push_str(format!("REM LEAD-OUT {:02}:{:02}:{:02}\n", m, s, f).as_str());
+                    result.push_str(format!("REM SESSION {:02}\n", session.number).as_str());
+                }
+                result.push_str(
+                    format!("  TRACK {:02} {}\n", track.number, track.mode.as_cue_str()).as_str(),
+                );
+                let flags = track.flags.names();
+                if !flags.is_empty() {
+                    result.push_str(format!("    FLAGS {}\n", flags.join(" ")).as_str());
+                }
+                if let Some(isrc) = &track.isrc {
+                    result.push_str(format!("    ISRC {}\n", isrc).as_str());
+                }
+                for index in &track.indices {
+                    let (m, s, f) = lba_to_msf(index.start - file_start);
+                    result.push_str(
+                        format!("    INDEX {:02} {:02}:{:02}:{:02}\n", index.number, m, s, f)
+                            .as_str(),
+                    );
+                }
+                written = track.start + track.length;
+            }
         }
 
         result
@@ -205,41 +559,63 @@ impl Disc {
 
 pub struct SectorIterator<'a> {
     current: i64,
+    // The track and index the previous sector was in. Sectors are visited
+    // in order, so we only ever need to move forwards from here.
+    track: usize,
+    index: usize,
     disc: &'a Disc,
 }
 
 impl<'a> SectorIterator<'a> {
-    pub fn sector_from_number(&self, sector: i64) -> Option<Sector> {
-        // We should start at or around sector 0 (actually 150, but who's counting)
-        // (me, I am), which means we can iterate through tracks and indices in order
-        // safely until we hit the one that starts at our sector.
-        for track in &self.disc.tracks {
-            for (i, index) in track.indices.iter().enumerate() {
-                // Edge of the index is either the start of the next index (if there's
-                // another index) or the end of the track.
-                let boundary = if let Some(next) = track.indices.get(i + 1) {
-                    next.start - 1
-                } else {
-                    track.start + track.length
-                };
+    // The last sector in the given index of the given track.
+    fn index_end(&self, t: usize, i: usize) -> i64 {
+        let track = &self.disc.tracks[t];
+        // Edge of the index is either the start of the next index (if there's
+        // another index) or the end of the track. Within a session, the
+        // first sector of the next track still shows this track's position,
+        // as on real discs, but the first track of a new session has the
+        // gap between sessions before it and begins right at its index 1.
+        if let Some(next) = track.indices.get(i + 1) {
+            next.start - 1
+        } else {
+            match self.disc.tracks.get(t + 1) {
+                Some(next)
+                    if next.indices[0].number != 0
+                        && self.disc.session_number(next) != self.disc.session_number(track) =>
+                {
+                    next.indices[0].start - 1
+                }
+                _ => track.start + track.length,
+            }
+        }
+    }
 
-                if index.start <= sector && boundary >= sector {
-                    // Yes, it's okay for this to be negative! Pregap counts backwards
-                    // to the start of the following index.
-                    let relative_position = sector - track.start;
-
-                    return Some(Sector {
-                        start: sector,
-                        // Convenience for indexing relative to the start of the disc,
-                        // rather than the start of the disc image.
-                        // Yes, it means the first sector isn't sector 1.
-                        absolute_start: sector + 150,
-                        relative_position,
-                        size: 2352, // TODO un-hardcode this
-                        // Worry about lifetimes later, this is small anyway
-                        track: track.clone(),
-                        index: index.clone(),
-                    });
+    fn sector(&self, sector: i64, track: &'a Track, index: &'a Index) -> Sector<'a> {
+        Sector {
+            start: sector,
+            // Convenience for indexing relative to the start of the disc,
+            // rather than the start of the disc image.
+            // Yes, it means the first sector isn't sector 1.
+            absolute_start: self.disc.absolute_sector(sector),
+            // Yes, it's okay for this to be negative! Pregap counts backwards
+            // to the start of the following index.
+            relative_position: sector - track.start,
+            size: track.mode.sector_size(),
+            track,
+            index,
+            catalog: self.disc.catalog,
+            mcn_placement: self.disc.mcn_placement,
+        }
+    }
+
+    /// Looks up any sector on the disc, regardless of where the iterator
+    /// is. This has to search from the first track, so iterating is much
+    /// faster when visiting sectors in order.
+    pub fn sector_from_number(&self, sector: i64) -> Option<Sector<'a>> {
+        for (t, track) in self.disc.tracks.iter().enumerate() {
+            for (i, index) in track.indices.iter().enumerate() {
+                if index.start <= sector && self.index_end(t, i) >= sector {
+                    return Some(self.sector(sector, track, index));
                 }
             }
         }
@@ -249,77 +625,200 @@ impl<'a> SectorIterator<'a> {
 }
 
 impl<'a> Iterator for SectorIterator<'a> {
-    type Item = Sector;
+    type Item = Sector<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.current >= self.disc.sector_count {
             return None;
         }
 
-        let sector = self.sector_from_number(self.current);
-
+        let sector = self.current;
         self.current += 1;
 
-        sector
+        // Skip past every index that ends before this sector
+        while self.track < self.disc.tracks.len() && self.index_end(self.track, self.index) < sector
+        {
+            if self.index + 1 < self.disc.tracks[self.track].indices.len() {
+                self.index += 1;
+            } else {
+                self.track += 1;
+                self.index = 0;
+            }
+        }
+
+        let track = self.disc.tracks.get(self.track)?;
+        let index = &track.indices[self.index];
+        if index.start > sector {
+            return None;
+        }
+
+        Some(self.sector(sector, track, index))
     }
 }
 
-fn sector_length(path: &Path) -> i64 {
-    let metadata = match path.metadata() {
-        Ok(m) => m,
-        Err(_) => return 0,
-    };
+// Calculates the length of a file in sectors, along with the byte offset
+// within the file at which each of the tracks stored in it begins.
+// We can't just divide the file size by 2352: cooked tracks use smaller
+// sectors, and a single file can mix cooked data with raw audio.
+fn file_layout(
+    files: &FileSource,
+    file: &CueFile,
+    tracks: &[&CueTrack],
+) -> io::Result<(i64, Vec<u64>)> {
+    let file_size = source_length(files, &file.name, file.file_type)
+        .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", file.name, error)))?;
+
+    let mut offsets = vec![];
+    let mut offset = 0;
+    let mut sectors = 0;
+    for (i, track) in tracks.iter().enumerate() {
+        let sector_size = track.mode.sector_size() as u64;
+        offsets.push(offset);
+
+        // Each track runs up to the first index of the next track in the
+        // same file; the final track runs to the end of the file.
+        let length = if let Some(next) = tracks.get(i + 1) {
+            next.first_index() - sectors
+        } else {
+            (file_size.saturating_sub(offset) / sector_size) as i64
+        };
+        sectors += length;
+        offset += length as u64 * sector_size;
+    }
+
+    Ok((sectors, offsets))
+}
 
-    metadata.len() as i64 / 2352
+// Finds the length in bytes of the sector data in a file referenced by the
+// cuesheet. This is the entire file, except for WAVE files, where we only
+// want the audio samples, and compressed audio, which has to be decoded.
+fn source_length(files: &FileSource, name: &str, file_type: FileType) -> io::Result<u64> {
+    let path = Path::new(name);
+    if file_type == FileType::Aiff {
+        Err(unsupported_file_type(file_type))
+    } else if let Some(kind) = CompressedFormat::from_path(path) {
+        DecodedAudio::from_source(Box::new(files.open(path)?), path, kind)
+            .and_then(|audio| audio.length())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    } else if wave::is_wave(path) {
+        let data = WaveData::read(&mut files.open(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(data.length)
+    } else {
+        files.length(path)
+    }
+}
+
+// Opens a file referenced by the cuesheet, returning a reader which
+// produces its sector data. MOTOROLA files are returned as they are, since
+// only their audio tracks are byte-swapped.
+fn open_source(files: &FileSource, name: &str, file_type: FileType) -> io::Result<Box<dyn Read>> {
+    let path = Path::new(name);
+    if file_type == FileType::Aiff {
+        Err(unsupported_file_type(file_type))
+    } else if let Some(kind) = CompressedFormat::from_path(path) {
+        let audio = DecodedAudio::from_source(Box::new(files.open(path)?), path, kind)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Box::new(audio))
+    } else if wave::is_wave(path) {
+        let mut file = files.open(path)?;
+        let data =
+            WaveData::read(&mut file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        file.seek(SeekFrom::Start(data.offset))?;
+        Ok(Box::new(file.take(data.length)))
+    } else {
+        Ok(Box::new(files.open(path)?))
+    }
+}
+
+fn unsupported_file_type(file_type: FileType) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} files aren't supported", file_type.as_cue_str()),
+    )
 }
 
 impl Disc {
-    pub fn from_cuesheet(cuesheet: CD, root: &Path) -> Disc {
-        let mut previous_file: Option<String> = None;
+    /// Builds a disc from a cuesheet, reading the files it references
+    /// from `files` to find out how long they are. Fails if any of them
+    /// can't be read.
+    pub fn from_cuesheet(cuesheet: &Cuesheet, files: &FileSource) -> io::Result<Disc> {
+        let cue_tracks = &cuesheet.tracks;
+        let mut previous_file = None;
         let mut disc_length_so_far = 0;
-        let mut current_track_length = 0;
+        let mut current_file_length = 0;
+        let mut current_file_offsets = vec![];
+        let mut track_in_file = 0;
+        // Sectors from PREGAP and POSTGAP commands, which are on the disc
+        // but not in any file
+        let mut virtual_sectors_so_far = 0;
 
         let mut tracks = vec![];
-        for (i, track) in cuesheet.tracks().iter().enumerate() {
-            let current_file = track.get_filename();
-            current_track_length = sector_length(&root.join(&current_file));
+        for (i, track) in cue_tracks.iter().enumerate() {
+            let current_file = &cuesheet.files[track.file];
 
             // At the start of a new file, track the offset
-            if let Some(previous) = &previous_file {
-                if previous != &current_file {
-                    disc_length_so_far += sector_length(&root.join(previous));
-                }
+            if previous_file != Some(track.file) {
+                disc_length_so_far += current_file_length;
+
+                let file_tracks = cue_tracks[i..]
+                    .iter()
+                    .take_while(|t| t.file == track.file)
+                    .collect::<Vec<_>>();
+                (current_file_length, current_file_offsets) =
+                    file_layout(files, current_file, &file_tracks)?;
+                track_in_file = 0;
+                previous_file = Some(track.file);
             }
 
-            let tracknum = i as u8 + 1;
-
-            let start = track.get_start() + disc_length_so_far;
-            // The last track on the disc will have indeterminate length,
-            // because the cuesheet doesn't store that; we need to calculate
-            // it from the size of the current disc/track image.
-            let length = track
-                .get_length()
-                .unwrap_or(disc_length_so_far + current_track_length - start);
+            let tracknum = track.number;
+
+            // A PREGAP command adds a pregap that isn't in the file, unlike
+            // INDEX 00. If a track has both, the INDEX 00 wins.
+            let virtual_pregap = match (track.pregap, track.index(0)) {
+                (Some(pregap), None) => pregap,
+                _ => 0,
+            };
+            let virtual_postgap = track.postgap.unwrap_or(0);
+            virtual_sectors_so_far += virtual_pregap;
+
+            let file_start = track.start() + disc_length_so_far;
+            let start = file_start + virtual_sectors_so_far;
+            // Each track runs up to the first index of the next track in the
+            // same file. The last track in a file runs to the end of it,
+            // which the cuesheet doesn't store; we need to calculate it from
+            // the size of the file.
+            let file_length = cue_tracks
+                .get(i + 1)
+                .filter(|next| next.file == track.file)
+                .map(|next| next.first_index() - track.start());
+            let length = file_length
+                .unwrap_or(disc_length_so_far + current_file_length - file_start)
+                + virtual_postgap;
 
             let mut indices = vec![];
-            for i in 0..99 {
-                if let Some(index) = track.get_index(i) {
-                    let index = index + disc_length_so_far as isize;
-                    // Cuesheet doesn't actually track the end of an index,
-                    // so we need to either calculate the boundary of the next
-                    // index within the track or the end of the track itself.
-                    let end = if let Some(next) = track.get_index(i + 1) {
-                        next as i64 - 1 + start
-                    } else {
-                        start + track.get_length().unwrap_or(current_track_length)
-                    };
+            if virtual_pregap > 0 {
+                indices.push(Index {
+                    number: 0,
+                    start: start - virtual_pregap,
+                    end: start - 1,
+                });
+            }
+            for (j, &(number, index)) in track.indices.iter().enumerate() {
+                let offset = disc_length_so_far + virtual_sectors_so_far;
+                // Cuesheet doesn't actually track the end of an index,
+                // so we need to either calculate the boundary of the next
+                // index within the track or the end of the track itself.
+                let end = match track.indices.get(j + 1) {
+                    Some(&(_, next)) => next + offset - 1,
+                    None => start + length - 1,
+                };
 
-                    indices.push(Index {
-                        number: i as u8,
-                        start: index as i64,
-                        end,
-                    });
-                }
+                indices.push(Index {
+                    number,
+                    start: index + offset,
+                    end,
+                });
             }
 
             tracks.push(Track {
@@ -327,18 +826,209 @@ impl Disc {
                 start,
                 length,
                 indices,
-                mode: TrackMode::from_cue_mode(track.get_mode()),
+                mode: track.mode,
+                file_offset: current_file_offsets[track_in_file],
+                filename: current_file.name.clone(),
+                file_type: current_file.file_type,
+                // Invalid ISRCs are left out, so callers that care
+                // should check them first.
+                isrc: track.isrc.as_ref().and_then(|isrc| isrc.parse().ok()),
+                virtual_pregap,
+                virtual_postgap,
+                flags: track.flags,
             });
+            track_in_file += 1;
+            virtual_sectors_so_far += virtual_postgap;
+        }
 
-            if previous_file != Some(current_file.to_string()) {
-                previous_file = Some(current_file.to_string());
+        Ok(Disc {
+            tracks,
+            sector_count: disc_length_so_far + current_file_length + virtual_sectors_so_far,
+            cd_text: vec![],
+            catalog: None,
+            mcn_placement: Placement::default(),
+            sessions: vec![],
+        })
+    }
+}
+
+impl Disc {
+    /// Builds a disc from a parsed CCD file. The CCD doesn't name the
+    /// image file, so each track's `filename` is left empty.
+    pub fn from_ccd(ccd: &Ccd) -> Result<Disc, CcdError> {
+        let leadout = ccd.leadout().ok_or(CcdError::MissingLeadOut)?;
+
+        // The [TRACK n] sections are optional, but they're the only place
+        // index positions are recorded. Without them we fall back to the TOC,
+        // which only tells us where index 1 is and whether a track is audio
+        // or data; the data mode is taken from the session's pregap mode,
+        // which is the mode of the session's first track.
+        let mut layout = vec![];
+        if ccd.tracks.is_empty() {
+            for entry in &ccd.entries {
+                if !(1..=99).contains(&entry.point) {
+                    continue;
+                }
+                let mode = if entry.control & 0x04 == 0 {
+                    TrackMode::Audio
+                } else {
+                    let pregap_mode = ccd
+                        .sessions
+                        .iter()
+                        .find(|session| session.number == entry.session)
+                        .map(|session| session.pregap_mode);
+                    if pregap_mode == Some(2) {
+                        TrackMode::Mode2Raw
+                    } else {
+                        TrackMode::Mode1Raw
+                    }
+                };
+                layout.push((entry.point, mode, vec![(1, entry.plba)]));
+            }
+        } else {
+            for track in &ccd.tracks {
+                let section = format!("TRACK {}", track.number);
+                let mode =
+                    TrackMode::from_ccd_mode(track.mode).ok_or_else(|| CcdError::InvalidValue {
+                        section: section.clone(),
+                        key: "MODE".to_string(),
+                        value: track.mode.to_string(),
+                    })?;
+                if track.indices.is_empty() {
+                    return Err(CcdError::MissingKey {
+                        section,
+                        key: "INDEX 1".to_string(),
+                    });
+                }
+                layout.push((track.number, mode, track.indices.clone()));
+            }
+        }
+        if layout.is_empty() {
+            return Err(CcdError::NoTracks);
+        }
+        layout.sort_by_key(|(number, _, _)| *number);
+
+        // Positions in the CCD are on the disc, which on multisession discs
+        // includes the areas between sessions that aren't in the image.
+        // The gap before each session runs from the previous session's
+        // leadout to the first sector of the session in the image.
+        let track_sessions = ccd
+            .entries
+            .iter()
+            .filter(|entry| (1..=99).contains(&entry.point))
+            .map(|entry| (entry.point, entry.session))
+            .collect::<HashMap<_, _>>();
+        let mut sessions: Vec<Session> = vec![];
+        let mut gaps = 0;
+        for (number, _, positions) in layout.iter_mut() {
+            let session = track_sessions.get(number).copied().unwrap_or(1);
+            let current = sessions.last().map(|s| s.number).unwrap_or(1);
+            if session > current {
+                let previous_leadout = ccd
+                    .entries
+                    .iter()
+                    .find(|entry| entry.point == 0xA2 && entry.session == current)
+                    .ok_or(CcdError::MissingLeadOut)?
+                    .plba;
+                let gap = positions[0].1 - previous_leadout;
+                sessions.push(Session {
+                    number: session,
+                    first_track: *number,
+                    gap,
+                });
+                gaps += gap;
+            }
+            for position in positions.iter_mut() {
+                position.1 -= gaps;
+            }
+        }
+        let sector_count = leadout - gaps;
+
+        let mut isrcs = HashMap::new();
+        for track in &ccd.tracks {
+            if let Some(isrc) = &track.isrc {
+                let parsed: Isrc = isrc.parse().map_err(|_| CcdError::InvalidValue {
+                    section: format!("TRACK {}", track.number),
+                    key: "ISRC".to_string(),
+                    value: isrc.clone(),
+                })?;
+                isrcs.insert(track.number, parsed);
             }
         }
 
-        Disc {
-            tracks,
-            sector_count: disc_length_so_far + current_track_length,
+        let mut tracks = vec![];
+        for (i, (number, mode, positions)) in layout.iter().enumerate() {
+            // Each track runs up until the first index of the next track
+            let end = layout
+                .get(i + 1)
+                .map(|(_, _, next)| next[0].1)
+                .unwrap_or(sector_count);
+            let indices = positions
+                .iter()
+                .enumerate()
+                .map(|(j, &(number, start))| Index {
+                    number,
+                    start,
+                    end: positions.get(j + 1).map(|next| next.1).unwrap_or(end) - 1,
+                })
+                .collect::<Vec<_>>();
+            let start = indices
+                .iter()
+                .find(|index| index.number == 1)
+                .unwrap_or(&indices[0])
+                .start;
+
+            // The TOC has every flag except SCMS, which is only recorded
+            // in the FLAGS key some tools write.
+            let mut flags = ccd
+                .entries
+                .iter()
+                .find(|entry| entry.point == *number)
+                .map(|entry| TrackFlags::from_control(entry.control))
+                .unwrap_or_default();
+            if let Some(track) = ccd.tracks.iter().find(|track| track.number == *number) {
+                for name in &track.flags {
+                    // Names we don't know are ignored
+                    flags.insert(name);
+                }
+            }
+
+            tracks.push(Track {
+                number: *number,
+                start,
+                length: end - start,
+                file_offset: positions[0].1 as u64 * RAW_SECTOR_SIZE as u64,
+                indices,
+                mode: *mode,
+                filename: String::new(),
+                file_type: FileType::Binary,
+                isrc: isrcs.get(number).copied(),
+                virtual_pregap: 0,
+                virtual_postgap: 0,
+                flags,
+            });
         }
+
+        let catalog = ccd
+            .catalog
+            .as_ref()
+            .map(|catalog| {
+                catalog.parse().map_err(|_| CcdError::InvalidValue {
+                    section: "Disc".to_string(),
+                    key: "CATALOG".to_string(),
+                    value: catalog.clone(),
+                })
+            })
+            .transpose()?;
+
+        Ok(Disc {
+            tracks,
+            sector_count,
+            cd_text: vec![],
+            catalog,
+            mcn_placement: Placement::default(),
+            sessions,
+        })
     }
 }
 
@@ -349,10 +1039,143 @@ pub struct Track {
     pub length: i64,
     pub indices: Vec<Index>,
     pub mode: TrackMode,
+    // File containing this track's data, relative to the cuesheet
+    pub filename: String,
+    // How the data in that file is stored, from the cuesheet's FILE command
+    pub file_type: FileType,
+    // Offset in bytes within the file's sector data at which this track's
+    // data begins, including its pregap. For WAVE and compressed audio files,
+    // this is relative to the start of the decoded samples.
+    pub file_offset: u64,
+    // Written into mode 3 Q subchannel frames in audio tracks
+    pub isrc: Option<Isrc>,
+    // Sectors at the start and end of the track which aren't in the file,
+    // from the cuesheet's PREGAP and POSTGAP commands. They're included in
+    // the track's indices and length, and generated when writing the image.
+    pub virtual_pregap: i64,
+    pub virtual_postgap: i64,
+    // From the cuesheet's FLAGS command
+    pub flags: TrackFlags,
+}
+
+// Bits of the control field; see section 22.3.1 of ECMA-130.
+const CONTROL_PRE_EMPHASIS: u8 = 0x01;
+const CONTROL_COPY_PERMITTED: u8 = 0x02;
+const CONTROL_DATA: u8 = 0x04;
+const CONTROL_FOUR_CHANNEL: u8 = 0x08;
+
+impl Track {
+    /// The track's control field, as written in its TOC entry and Q
+    /// subchannel frames: whether it's a data track, along with its flags.
+    pub fn control(&self) -> u8 {
+        let mut control = 0;
+        if self.flags.pre_emphasis {
+            control |= CONTROL_PRE_EMPHASIS;
+        }
+        if self.flags.copy_permitted {
+            control |= CONTROL_COPY_PERMITTED;
+        }
+        if !matches!(self.mode, TrackMode::Audio) {
+            control |= CONTROL_DATA;
+        }
+        if self.flags.four_channel {
+            control |= CONTROL_FOUR_CHANNEL;
+        }
+
+        control
+    }
+
+    // A track with only an index 1, stored at the start of disc.bin.
+    #[cfg(test)]
+    pub(crate) fn test_track(number: u8, start: i64, length: i64, mode: TrackMode) -> Track {
+        Track {
+            number,
+            start,
+            length,
+            indices: vec![Index {
+                number: 1,
+                start,
+                end: start + length - 1,
+            }],
+            mode,
+            filename: "disc.bin".to_string(),
+            file_type: FileType::Binary,
+            file_offset: 0,
+            isrc: None,
+            virtual_pregap: 0,
+            virtual_postgap: 0,
+            flags: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Disc {
+    // A disc with nothing but a single track, for tests which only care
+    // about the sectors' positions.
+    pub(crate) fn test_single_track(mode: TrackMode, sectors: i64) -> Disc {
+        Disc {
+            tracks: vec![Track::test_track(1, 0, sectors, mode)],
+            sector_count: sectors,
+            cd_text: vec![],
+            catalog: None,
+            mcn_placement: Placement::default(),
+            sessions: vec![],
+        }
+    }
+}
+
+/// Flags set by a cuesheet's FLAGS command.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrackFlags {
+    /// PRE: the audio has pre-emphasis applied
+    pub pre_emphasis: bool,
+    /// DCP: digital copying is permitted
+    pub copy_permitted: bool,
+    /// 4CH: the audio has four channels rather than two
+    pub four_channel: bool,
+    /// SCMS: copying is controlled by the serial copy management system
+    pub scms: bool,
+}
+
+impl TrackFlags {
+    fn from_control(control: u8) -> TrackFlags {
+        TrackFlags {
+            pre_emphasis: control & CONTROL_PRE_EMPHASIS != 0,
+            copy_permitted: control & CONTROL_COPY_PERMITTED != 0,
+            four_channel: control & CONTROL_FOUR_CHANNEL != 0,
+            scms: false,
+        }
+    }
+
+    // Sets the flag with the given name, returning false if there's no
+    // such flag.
+    fn insert(&mut self, name: &str) -> bool {
+        match name.to_ascii_uppercase().as_str() {
+            "DCP" => self.copy_permitted = true,
+            "4CH" => self.four_channel = true,
+            "PRE" => self.pre_emphasis = true,
+            "SCMS" => self.scms = true,
+            _ => return false,
+        }
+        true
+    }
+
+    /// The names of the flags that are set, as written in cuesheets.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.copy_permitted, "DCP"),
+            (self.four_channel, "4CH"),
+            (self.pre_emphasis, "PRE"),
+            (self.scms, "SCMS"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect()
+    }
 }
 
-// Ugly workaround to avoid embedding cue types, rework later
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrackMode {
     Audio,
     /// 2048-byte data without ECC
@@ -372,16 +1195,30 @@ pub enum TrackMode {
 }
 
 impl TrackMode {
-    fn from_cue_mode(mode: track::TrackMode) -> TrackMode {
+    // Parses the mode from a cuesheet's TRACK command. CD-i discs use the
+    // same modes as CD-ROM XA, under a different name.
+    fn from_cue_str(mode: &str) -> Option<TrackMode> {
+        match mode.to_ascii_uppercase().as_str() {
+            "AUDIO" => Some(TrackMode::Audio),
+            "MODE1/2048" => Some(TrackMode::Mode1),
+            "MODE1/2352" => Some(TrackMode::Mode1Raw),
+            "MODE2/2336" | "CDI/2336" => Some(TrackMode::Mode2),
+            "MODE2/2048" => Some(TrackMode::Mode2Form1),
+            "MODE2/2324" => Some(TrackMode::Mode2Form2),
+            "MODE2/2332" => Some(TrackMode::Mode2FormMix),
+            "MODE2/2352" | "CDI/2352" => Some(TrackMode::Mode2Raw),
+            _ => None,
+        }
+    }
+
+    // CCD files store the mode as a number; everything in a
+    // CloneCD image is raw.
+    fn from_ccd_mode(mode: u8) -> Option<TrackMode> {
         match mode {
-            track::TrackMode::Audio => TrackMode::Audio,
-            track::TrackMode::Mode1 => TrackMode::Mode1,
-            track::TrackMode::Mode1Raw => TrackMode::Mode1Raw,
-            track::TrackMode::Mode2 => TrackMode::Mode2,
-            track::TrackMode::Mode2Form1 => TrackMode::Mode2Form1,
-            track::TrackMode::Mode2Form2 => TrackMode::Mode2Form2,
-            track::TrackMode::Mode2FormMix => TrackMode::Mode2FormMix,
-            track::TrackMode::Mode2Raw => TrackMode::Mode2Raw,
+            0 => Some(TrackMode::Audio),
+            1 => Some(TrackMode::Mode1Raw),
+            2 => Some(TrackMode::Mode2Raw),
+            _ => None,
         }
     }
 
@@ -396,6 +1233,31 @@ impl TrackMode {
             | TrackMode::Mode2FormMix => 2,
         }
     }
+
+    /// The name of this mode in a cuesheet's TRACK command.
+    pub fn as_cue_str(&self) -> &'static str {
+        match self {
+            TrackMode::Audio => "AUDIO",
+            TrackMode::Mode1 => "MODE1/2048",
+            TrackMode::Mode1Raw => "MODE1/2352",
+            TrackMode::Mode2 => "MODE2/2336",
+            TrackMode::Mode2Form1 => "MODE2/2048",
+            TrackMode::Mode2Form2 => "MODE2/2324",
+            TrackMode::Mode2FormMix => "MODE2/2332",
+            TrackMode::Mode2Raw => "MODE2/2352",
+        }
+    }
+
+    /// Size of each sector of this track in the source image, in bytes.
+    pub fn sector_size(&self) -> usize {
+        match self {
+            TrackMode::Audio | TrackMode::Mode1Raw | TrackMode::Mode2Raw => RAW_SECTOR_SIZE,
+            TrackMode::Mode1 | TrackMode::Mode2Form1 => 2048,
+            TrackMode::Mode2 => 2336,
+            TrackMode::Mode2Form2 => 2324,
+            TrackMode::Mode2FormMix => 2332,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -409,26 +1271,29 @@ pub struct Index {
 }
 
 #[derive(Debug)]
-pub struct Sector {
+pub struct Sector<'a> {
     // Sector number, relative to the start of the image
     pub start: i64,
     // Sector number, relative to the start of the disc
     pub absolute_start: i64,
     // Relative position to index 1 of the current track
     pub relative_position: i64,
-    // Size of the sector, in bytes
+    // Size of the sector in the source image, in bytes
     pub size: usize,
     // Metadata for the current track
-    pub track: Track,
+    pub track: &'a Track,
     // Metadata for the current index
-    pub index: Index,
+    pub index: &'a Index,
+    // The disc's media catalog number, and where it's placed
+    pub catalog: Option<Mcn>,
+    pub mcn_placement: Placement,
 }
 
 fn bcd(dec: i64) -> u8 {
     (((dec / 10) << 4) | (dec % 10)) as u8
 }
 
-impl Sector {
+impl Sector<'_> {
     // The subchannel data contains extra sidecar metadata required to read
     // the disc, but which isn't a part of the data itself.
     // Some applications can read CloneCD data with zeroed out subchannel data
@@ -451,37 +1316,109 @@ impl Sector {
         &self,
         chosen_protection_type: &Option<DiscProtection>,
         preconstructed_q_subchannels: &HashMap<i64, Vec<u8>>,
-    ) -> Vec<u8> {
+    ) -> [u8; 96] {
+        // The vast majority of real discs write their unused R-W fields as 0s,
+        // but at least one real disc used FFs instead. We'll side with the
+        // majority and use 0.
+        let mut out = [0; 96];
+
         // The first sector of a track, and only the first sector,
         // gets an FFed out P sector like a pregap. Every other non-pregap
         // sector uses 0s. (Section 22.2)
         // For players which ignore the Q subchannel, this allows
         // locating the start of tracks.
-        let mut p = if self.relative_position == 0 || self.index.number == 0 {
-            vec![0xFF; 12]
-        } else {
-            vec![0; 12]
-        };
-        let mut q = Sector::generate_q_subchannel(
+        if self.relative_position == 0 || self.index.number == 0 {
+            out[0..12].fill(0xFF);
+        }
+        let q = self
+            .generate_q_mode_frame(chosen_protection_type, preconstructed_q_subchannels)
+            .unwrap_or_else(|| {
+                Sector::generate_q_subchannel(
+                    self.absolute_start,
+                    self.relative_position,
+                    self.track.number,
+                    self.index.number,
+                    self.control(),
+                    chosen_protection_type,
+                    preconstructed_q_subchannels,
+                )
+            });
+        out[12..24].copy_from_slice(&q);
+
+        out
+    }
+
+    // Generates a Q subchannel frame storing the catalog number or ISRC
+    // instead of the sector's position, if this sector should have one.
+    fn generate_q_mode_frame(
+        &self,
+        chosen_protection_type: &Option<DiscProtection>,
+        preconstructed_q_subchannels: &HashMap<i64, Vec<u8>>,
+    ) -> Option<[u8; 12]> {
+        // Players find the start of each index from its first mode 1 frame,
+        // so that one's always kept. Copy protection layouts are generated
+        // assuming every frame is mode 1, so they're left alone too, and
+        // LSD/SBI data always takes priority.
+        if self.start == self.index.start
+            || chosen_protection_type.is_some()
+            || preconstructed_q_subchannels.contains_key(&self.absolute_start)
+        {
+            return None;
+        }
+
+        let control = self.control();
+        if let Some(mcn) = &self.catalog {
+            if self.mcn_placement.includes_mcn(self.absolute_start) {
+                return Some(qmode::mcn_q_subchannel(mcn, control, self.absolute_start));
+            }
+        }
+        // No ISRCs in pregaps, or in the last frame of a track
+        // (the first is already covered above). Tracks run up to and
+        // including the sector at start + length, except for the last
+        // track, which stops just short of it, so both are excluded.
+        let end = self.track.start + self.track.length;
+        if let Some(isrc) = &self.track.isrc {
+            if matches!(self.track.mode, TrackMode::Audio)
+                && self.index.number != 0
+                && self.start < end - 1
+                && self.mcn_placement.includes_isrc(self.absolute_start)
+            {
+                return Some(qmode::isrc_q_subchannel(isrc, control, self.absolute_start));
+            }
+        }
+
+        None
+    }
+
+    /// Generates the Q subchannel data for this sector from the disc's
+    /// layout alone, ignoring any LSD/SBI data. Every frame is mode 1,
+    /// since real discs place their other frames inconsistently.
+    pub fn generate_q_subchannel_from_layout(
+        &self,
+        chosen_protection_type: &Option<DiscProtection>,
+    ) -> [u8; 12] {
+        Sector::generate_q_subchannel_from_scratch(
             self.absolute_start,
             self.relative_position,
             self.track.number,
             self.index.number,
-            self.track.mode,
+            self.control(),
             chosen_protection_type,
-            preconstructed_q_subchannels,
-        );
-        // The vast majority of real discs write their unused R-W fields as 0s,
-        // but at least one real disc used FFs instead. We'll side with the
-        // majority and use 0.
-        let mut rest = vec![0; 72];
-
-        let mut out = vec![];
-        out.append(&mut p);
-        out.append(&mut q);
-        out.append(&mut rest);
+        )
+    }
 
-        out
+    // The control field of this sector's Q subchannel frame. On discs
+    // using SCMS, the copy permitted bit is switched on and off every four
+    // frames instead of staying the same throughout the track.
+    fn control(&self) -> u8 {
+        let control = self.track.control();
+        if !self.track.flags.scms {
+            control
+        } else if (self.absolute_start / 4) % 2 == 0 {
+            control | CONTROL_COPY_PERMITTED
+        } else {
+            control & !CONTROL_COPY_PERMITTED
+        }
     }
 
     fn generate_q_subchannel(
@@ -489,23 +1426,25 @@ impl Sector {
         relative_sector: i64,
         track: u8,
         index: u8,
-        track_type: TrackMode,
+        control: u8,
         chosen_protection_type: &Option<DiscProtection>,
         preconstructed_q_subchannels: &HashMap<i64, Vec<u8>>,
-    ) -> Vec<u8> {
+    ) -> [u8; 12] {
         // LSD/SBI checked without checking for specific protection chosen because technically
         // speaking, there's no reason you *shouldn't* be able to provide an LSD/SBI file even if
         // you didn't choose protection
 
-        if let Some(q) = preconstructed_q_subchannels.get(&absolute_sector) {
-            q.clone()
+        if let Some(preconstructed) = preconstructed_q_subchannels.get(&absolute_sector) {
+            let mut q = [0; 12];
+            q.copy_from_slice(&preconstructed[..12]);
+            q
         } else {
             Sector::generate_q_subchannel_from_scratch(
                 absolute_sector,
                 relative_sector,
                 track,
                 index,
-                track_type,
+                control,
                 chosen_protection_type,
             )
         }
@@ -516,31 +1455,24 @@ impl Sector {
         relative_sector: i64,
         track: u8,
         index: u8,
-        track_type: TrackMode,
+        control: u8,
         chosen_protection_type: &Option<DiscProtection>,
-    ) -> Vec<u8> {
+    ) -> [u8; 12] {
         // This channel made up of a sequence of bits; we'll start by
         // zeroing it out, then setting individual bits.
-        let mut q = vec![0; 12];
-
-        // First four bits are the control field.
-        // We only care about setting the data bit, 1; the others are
-        // irrelevant for this application.
-        match track_type {
-            TrackMode::Audio => (),
-            _ => q[0] |= 1 << 6,
-        };
+        let mut q = [0; 12];
+
+        // First four bits are the control field, which is the same
+        // as the track's TOC entry (see Track::control).
+        q[0] |= control << 4;
 
         // Next four bits indicate the mode of the Q channel.
         // There are three modes:
         // * 1 - Table of contents (used during the lead-in)
         // * 2 - Media Catalog Number
         // * 3 - International Standard Recording Code (ISRC)
-        // In practice, we're always generating mode 1
-        // every sector so we'll hardcode this.
-        // Note that the cuesheet *can* contain the catalog number,
-        // so it'd be possible for us to set this, but libcue doesn't
-        // expose a getter for that; it's simpler just to skip it.
+        // Mode 2 frames are generated separately (see qmode.rs),
+        // so here it's always mode 1.
         q[0] |= 1 << 0;
         // OK, it's data time! This is the next 9 bytes.
         // This contains timing info for the current track.
@@ -570,7 +1502,7 @@ impl Sector {
             }
             _ => {
                 q[2] = bcd(index as i64);
-            } //SecuROM and LibCrypt currently not implemented
+            }
         }
 
         // The next three fields, MIN, SEC, and FRAC, are the
@@ -610,7 +1542,7 @@ impl Sector {
             }
             _ => {
                 q[4] = bcd((relative_sector_count / 75) % 60);
-            } //SecuROM and LibCrypt currently not implemented
+            }
         }
         // FRAC
         q[5] = bcd(relative_sector_count % 75);
@@ -628,12 +1560,29 @@ impl Sector {
         q[10] = ((crc >> 8) & 0xFF) as u8;
         q[11] = (crc & 0xFF) as u8;
 
+        // LibCrypt alters sectors after the fact. SecuROM does too, but
+        // where varies from disc to disc, so its sectors only ever come
+        // from an LSD or SBI.
+        match chosen_protection_type {
+            Some(DiscProtection::LibCryptScheme1 { key })
+                if libcrypt::is_altered(*key, false, absolute_sector) =>
+            {
+                libcrypt::alter_q_subchannel(&mut q);
+            }
+            Some(DiscProtection::LibCryptScheme2 { key })
+                if libcrypt::is_altered(*key, true, absolute_sector) =>
+            {
+                libcrypt::alter_q_subchannel(&mut q);
+            }
+            _ => (),
+        }
+
         q
     }
 }
 
 //TODO: Possible protections, improve descriptions after review
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DiscProtection {
     DiscGuardScheme1,
     /// Change one second of sector MSFs
@@ -645,9 +1594,35 @@ pub enum DiscProtection {
     SecuROMScheme3b,
     SecuROMScheme3c,
     SecuROMScheme4,
-    /// Subchannel-error-based PS1 protection
-    LibCryptScheme1,
-    LibCryptScheme2,
+    /// Subchannel-error-based PS1 protection, with the key stored once
+    LibCryptScheme1 {
+        key: u16,
+    },
+    /// LibCrypt with the key stored twice
+    LibCryptScheme2 {
+        key: u16,
+    },
+}
+
+impl std::fmt::Display for DiscProtection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiscProtection::DiscGuardScheme1 => write!(f, "DiscGuard (scheme 1)"),
+            DiscProtection::DiscGuardScheme2 => write!(f, "DiscGuard (scheme 2)"),
+            DiscProtection::SecuROMScheme1 => write!(f, "SecuROM (scheme 1)"),
+            DiscProtection::SecuROMScheme2 => write!(f, "SecuROM (scheme 2)"),
+            DiscProtection::SecuROMScheme3a => write!(f, "SecuROM (scheme 3a)"),
+            DiscProtection::SecuROMScheme3b => write!(f, "SecuROM (scheme 3b)"),
+            DiscProtection::SecuROMScheme3c => write!(f, "SecuROM (scheme 3c)"),
+            DiscProtection::SecuROMScheme4 => write!(f, "SecuROM (scheme 4)"),
+            DiscProtection::LibCryptScheme1 { key } => {
+                write!(f, "LibCrypt (key {:04X}, stored once)", key)
+            }
+            DiscProtection::LibCryptScheme2 { key } => {
+                write!(f, "LibCrypt (key {:04X}, stored twice)", key)
+            }
+        }
+    }
 }
 
 // For more detail, see section 22.3.4.2 of ECMA-130.
@@ -656,6 +1631,8 @@ enum Pointer {
     FirstTrack,
     LastTrack,
     LeadOut,
+    NextProgramArea,
+    FirstLeadIn,
 }
 
 impl Pointer {
@@ -665,26 +1642,36 @@ impl Pointer {
             Self::FirstTrack => 0xA0,
             Self::LastTrack => 0xA1,
             Self::LeadOut => 0xA2,
+            Self::NextProgramArea => 0xB0,
+            Self::FirstLeadIn => 0xC0,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use cue::cd::CD;
     use std::collections::HashMap;
     use std::fs::{read_to_string, File};
     use std::io::Read;
     use std::{io::Write, path::PathBuf};
 
-    use crate::Disc;
+    use crate::ccd::Ccd;
+    use crate::cuesheet::Cuesheet;
+    use crate::session::Session;
+    use crate::source::FileSource;
+    use crate::{Disc, Track, TrackMode};
 
     #[derive(Debug)]
     struct TestPaths {
         data_plus_audio_cue: PathBuf,
         data_plus_audio_ccd: PathBuf,
+        data_plus_audio_wave: PathBuf,
+        data_plus_audio_flac: PathBuf,
+        data_plus_audio_pregap: PathBuf,
+        data_plus_audio_cdtext: PathBuf,
         one_track_cue: PathBuf,
         one_track_ccd: PathBuf,
+        one_track_cooked: PathBuf,
     }
 
     fn get_test_paths() -> TestPaths {
@@ -695,8 +1682,13 @@ mod tests {
         TestPaths {
             data_plus_audio_cue: root.join("dataplusaudio").join("bincue"),
             data_plus_audio_ccd: root.join("dataplusaudio").join("ccd"),
+            data_plus_audio_wave: root.join("dataplusaudio").join("wave"),
+            data_plus_audio_flac: root.join("dataplusaudio").join("flac"),
+            data_plus_audio_pregap: root.join("dataplusaudio").join("pregap"),
+            data_plus_audio_cdtext: root.join("dataplusaudio").join("cdtext"),
             one_track_cue: root.join("onetrack").join("bincue"),
             one_track_ccd: root.join("onetrack").join("ccd"),
+            one_track_cooked: root.join("onetrack").join("cooked"),
         }
     }
 
@@ -706,8 +1698,12 @@ mod tests {
         let in_cue = paths.one_track_cue.join("basic_image.cue");
         let cue_sheet = read_to_string(&in_cue).unwrap();
 
-        let cd = CD::parse(cue_sheet).unwrap();
-        let disc = Disc::from_cuesheet(cd, &paths.one_track_cue);
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.one_track_cue.clone()),
+        )
+        .unwrap();
 
         let mut buf = vec![];
         for sector in disc.sectors() {
@@ -729,8 +1725,12 @@ mod tests {
         let in_cue = paths.one_track_cue.join("basic_image.cue");
         let cue_sheet = read_to_string(in_cue).unwrap();
 
-        let cd = CD::parse(cue_sheet).unwrap();
-        let disc = Disc::from_cuesheet(cd, &paths.one_track_cue);
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.one_track_cue.clone()),
+        )
+        .unwrap();
 
         let ccd = disc.generate_ccd();
 
@@ -746,8 +1746,12 @@ mod tests {
         let in_cue = paths.data_plus_audio_cue.join("disc.cue");
         let cue_sheet = read_to_string(&in_cue).unwrap();
 
-        let cd = CD::parse(cue_sheet).unwrap();
-        let disc = Disc::from_cuesheet(cd, &paths.data_plus_audio_cue);
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.data_plus_audio_cue.clone()),
+        )
+        .unwrap();
 
         let mut buf = vec![];
         for sector in disc.sectors() {
@@ -769,8 +1773,12 @@ mod tests {
         let in_cue = paths.data_plus_audio_cue.join("disc.cue");
         let cue_sheet = read_to_string(in_cue).unwrap();
 
-        let cd = CD::parse(cue_sheet).unwrap();
-        let disc = Disc::from_cuesheet(cd, &paths.data_plus_audio_cue);
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.data_plus_audio_cue.clone()),
+        )
+        .unwrap();
 
         let ccd = disc.generate_ccd();
 
@@ -779,4 +1787,368 @@ mod tests {
 
         assert_eq!(real_ccd, ccd);
     }
+
+    #[test]
+    fn test_multitrack_from_ccd() {
+        let paths = get_test_paths();
+        let real_ccd = read_to_string(paths.data_plus_audio_ccd.join("disc.ccd")).unwrap();
+
+        let ccd = Ccd::parse(&real_ccd).unwrap();
+        let mut disc = Disc::from_ccd(&ccd).unwrap();
+        for track in disc.tracks.iter_mut() {
+            track.filename = "disc.bin".to_string();
+        }
+
+        assert_eq!(real_ccd, disc.generate_ccd());
+
+        let real_cue = read_to_string(paths.data_plus_audio_cue.join("disc.cue")).unwrap();
+        assert_eq!(real_cue, disc.generate_cue());
+
+        let mut buf = vec![];
+        for sector in disc.sectors() {
+            buf.write_all(&sector.generate_subchannel(&None, &HashMap::new()))
+                .unwrap();
+        }
+        let mut real_sub = vec![];
+        File::open(paths.data_plus_audio_ccd.join("disc.sub"))
+            .unwrap()
+            .read_to_end(&mut real_sub)
+            .unwrap();
+        assert_eq!(real_sub, buf);
+    }
+
+    #[test]
+    fn test_multisession_ccd() {
+        // An Enhanced CD: an audio track, then a data track in a second
+        // session, 11400 sectors further along the disc than in the image.
+        let disc = Disc {
+            tracks: vec![
+                Track::test_track(1, 0, 300, TrackMode::Audio),
+                Track::test_track(2, 300, 100, TrackMode::Mode2Raw),
+            ],
+            sessions: vec![Session {
+                number: 2,
+                first_track: 2,
+                gap: 11400,
+            }],
+            ..Disc::test_single_track(TrackMode::Audio, 400)
+        };
+
+        let ccd = disc.generate_ccd();
+        assert!(ccd.contains("TocEntries=10\nSessions=2\n"));
+        assert!(ccd.contains("[Session 2]\nPreGapMode=2\n"));
+        // The first session's leadout, B0 pointing 1:30 + 1:00 past it,
+        // and the data track
+        assert!(ccd.contains("Point=0xa2\nADR=0x01\nControl=0x00\nTrackNo=0\nAMin=0\nASec=0\nAFrame=0\nALBA=-150\nZero=0\nPMin=0\nPSec=6\nPFrame=0\nPLBA=300\n"));
+        assert!(ccd.contains("Point=0xb0\nADR=0x05\nControl=0x00\nTrackNo=0\nAMin=2\nASec=36\nAFrame=0\nALBA=11550\nZero=2\n"));
+        assert!(ccd.contains("Session=2\nPoint=0x02\nADR=0x01\nControl=0x04\nTrackNo=0\nAMin=0\nASec=0\nAFrame=0\nALBA=-150\nZero=0\nPMin=2\nPSec=38\nPFrame=0\nPLBA=11700\n"));
+        assert!(ccd.contains("Session=2\nPoint=0xa2\nADR=0x01\nControl=0x04\nTrackNo=0\nAMin=0\nASec=0\nAFrame=0\nALBA=-150\nZero=0\nPMin=2\nPSec=39\nPFrame=25\nPLBA=11800\n"));
+
+        // The Q subchannel's absolute time jumps across the gap
+        let sectors = disc.sectors().collect::<Vec<_>>();
+        assert_eq!(sectors[299].absolute_start, 449);
+        assert_eq!(sectors[300].track.number, 2);
+        assert_eq!(sectors[300].absolute_start, 11850);
+
+        let parsed = Disc::from_ccd(&Ccd::parse(&ccd).unwrap()).unwrap();
+        assert_eq!(parsed.sessions, disc.sessions);
+        assert_eq!(parsed.sector_count, 400);
+        assert_eq!(parsed.tracks[1].start, 300);
+
+        // In a single session, the first sector of the next track still
+        // shows the previous track's position
+        let single_session = Disc {
+            sessions: vec![],
+            ..disc
+        };
+        let sectors = single_session.sectors().collect::<Vec<_>>();
+        assert_eq!(sectors[300].track.number, 1);
+        assert_eq!(sectors[301].track.number, 2);
+    }
+
+    #[test]
+    fn test_multitrack_cue() {
+        let paths = get_test_paths();
+        let in_cue = paths.data_plus_audio_cue.join("disc.cue");
+        let cue_sheet = read_to_string(in_cue).unwrap();
+
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.data_plus_audio_cue.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(cue_sheet, disc.generate_cue());
+    }
+
+    #[test]
+    fn test_track_flags() {
+        let paths = get_test_paths();
+        let in_cue = paths.data_plus_audio_cue.join("disc.cue");
+        let cue_sheet = read_to_string(in_cue).unwrap().replace(
+            "  TRACK 02 AUDIO\n",
+            "  TRACK 02 AUDIO\n    FLAGS DCP PRE\n",
+        );
+
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let mut disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.data_plus_audio_cue.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(disc.tracks[1].control(), 0x03);
+        assert_eq!(cue_sheet, disc.generate_cue());
+        let ccd = disc.generate_ccd();
+        assert!(ccd.contains("Point=0x02\nADR=0x01\nControl=0x03\n"));
+        assert!(ccd.contains("[TRACK 2]\nMODE=0\nFLAGS= DCP PRE\n"));
+        // Every Q frame in the track, including its pregap, has the same
+        // control field
+        for sector in disc.sectors().skip(317).take(225) {
+            let q = sector.generate_q_subchannel_from_layout(&None);
+            assert_eq!(q[0] >> 4, 0x03);
+        }
+
+        // With SCMS, the copy permitted bit changes every four frames
+        disc.tracks[2].flags.scms = true;
+        let controls = disc
+            .sectors()
+            .skip(700)
+            .take(8)
+            .map(|sector| sector.generate_q_subchannel_from_layout(&None)[0] >> 4)
+            .collect::<Vec<_>>();
+        // Sector 700 is at 00:11:25 (850)
+        assert_eq!(
+            controls,
+            vec![0x02, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_multitrack_cd_text() {
+        let paths = get_test_paths();
+        let cue_sheet = read_to_string(paths.data_plus_audio_cdtext.join("disc.cue")).unwrap();
+        let files = FileSource::Directory(paths.data_plus_audio_cdtext.clone());
+
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let cd_text = crate::cdtext::packs_from_cuesheet(&cuesheet, &files).unwrap();
+        let mut disc = Disc::from_cuesheet(&cuesheet, &files).unwrap();
+        disc.cd_text = cd_text;
+        let ccd = disc.generate_ccd();
+
+        // "Album\0\0Song\0\0" takes two packs, plus three size packs, each
+        // listed without its CRC
+        let expected = read_to_string(paths.data_plus_audio_cdtext.join("disc.ccd")).unwrap();
+        assert_eq!(ccd, expected);
+        assert!(ccd.contains("CDTextLength=90\n\n[CDText]\nEntries=5\n"));
+        assert!(ccd.find("[CDText]") < ccd.find("[Session 1]"));
+        assert!(Ccd::parse(&ccd).is_ok());
+    }
+
+    #[test]
+    fn test_wave_audio_tracks() {
+        let paths = get_test_paths();
+        let in_cue = paths.data_plus_audio_wave.join("disc.cue");
+        let cue_sheet = read_to_string(in_cue).unwrap();
+
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.data_plus_audio_wave.clone()),
+        )
+        .unwrap();
+
+        let mut img = vec![];
+        disc.write_img(
+            &FileSource::Directory(paths.data_plus_audio_wave.clone()),
+            &mut img,
+        )
+        .unwrap();
+
+        let real_img_path = paths.data_plus_audio_ccd.join("disc.bin");
+        let mut real_img_file = File::open(real_img_path).unwrap();
+        let mut real_img = vec![];
+        real_img_file.read_to_end(&mut real_img).unwrap();
+
+        assert_eq!(real_img, img);
+
+        let mut sub = vec![];
+        for sector in disc.sectors() {
+            sub.write_all(&sector.generate_subchannel(&None, &HashMap::new()))
+                .unwrap();
+        }
+
+        let real_sub_path = paths.data_plus_audio_ccd.join("disc.sub");
+        let mut real_sub_file = File::open(real_sub_path).unwrap();
+        let mut real_sub = vec![];
+        real_sub_file.read_to_end(&mut real_sub).unwrap();
+
+        assert_eq!(real_sub, sub);
+    }
+
+    #[test]
+    fn test_pregap_commands() {
+        // The same disc, with the silent pregaps of the audio tracks left
+        // out of the files and described with PREGAP instead.
+        let paths = get_test_paths();
+        let in_cue = paths.data_plus_audio_pregap.join("disc.cue");
+        let cue_sheet = read_to_string(in_cue).unwrap();
+
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.data_plus_audio_pregap.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(disc.sector_count, 766);
+        assert_eq!(disc.tracks[1].indices[0].number, 0);
+        assert_eq!(disc.tracks[1].indices[0].start, 316);
+        assert_eq!(disc.tracks[2].start, 691);
+
+        let mut img = vec![];
+        disc.write_img(
+            &FileSource::Directory(paths.data_plus_audio_pregap.clone()),
+            &mut img,
+        )
+        .unwrap();
+
+        let real_img_path = paths.data_plus_audio_ccd.join("disc.bin");
+        let mut real_img_file = File::open(real_img_path).unwrap();
+        let mut real_img = vec![];
+        real_img_file.read_to_end(&mut real_img).unwrap();
+
+        assert_eq!(real_img, img);
+
+        let mut sub = vec![];
+        for sector in disc.sectors() {
+            sub.write_all(&sector.generate_subchannel(&None, &HashMap::new()))
+                .unwrap();
+        }
+
+        let real_sub_path = paths.data_plus_audio_ccd.join("disc.sub");
+        let mut real_sub_file = File::open(real_sub_path).unwrap();
+        let mut real_sub = vec![];
+        real_sub_file.read_to_end(&mut real_sub).unwrap();
+
+        assert_eq!(real_sub, sub);
+
+        let real_ccd_path = paths.data_plus_audio_ccd.join("disc.ccd");
+        let real_ccd = read_to_string(real_ccd_path).unwrap();
+
+        assert_eq!(real_ccd, disc.generate_ccd());
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let paths = get_test_paths();
+        let cue_sheet = read_to_string(paths.data_plus_audio_pregap.join("disc.cue"))
+            .unwrap()
+            .replace("track03.bin", "missing.bin");
+        let Err(error) = Disc::from_cuesheet(
+            &Cuesheet::parse(&cue_sheet).unwrap(),
+            &FileSource::Directory(paths.data_plus_audio_pregap.clone()),
+        ) else {
+            panic!("expected missing.bin not to be found");
+        };
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+        assert!(error.to_string().starts_with("missing.bin: "));
+    }
+
+    #[test]
+    fn test_file_between_track_and_index() {
+        // Some tools put each FILE after the TRACK it holds. The tracks
+        // are still read from the file their indices are in.
+        let paths = get_test_paths();
+        let files = FileSource::Directory(paths.data_plus_audio_pregap.clone());
+        let cue_sheet = read_to_string(paths.data_plus_audio_pregap.join("disc.cue")).unwrap();
+        let moved = cue_sheet
+            .replace(
+                "FILE \"track02.bin\" BINARY\n  TRACK 02 AUDIO\n",
+                "  TRACK 02 AUDIO\nFILE \"track02.bin\" BINARY\n",
+            )
+            .replace(
+                "FILE \"track03.bin\" BINARY\n  TRACK 03 AUDIO\n",
+                "  TRACK 03 AUDIO\nFILE \"track03.bin\" BINARY\n",
+            );
+        assert_ne!(cue_sheet, moved);
+
+        let expected = Disc::from_cuesheet(&Cuesheet::parse(&cue_sheet).unwrap(), &files).unwrap();
+        let disc = Disc::from_cuesheet(&Cuesheet::parse(&moved).unwrap(), &files).unwrap();
+        assert_eq!(disc.sector_count, expected.sector_count);
+        assert_eq!(disc.tracks[1].filename, "track02.bin");
+        assert_eq!(disc.tracks[2].filename, "track03.bin");
+        assert_eq!(disc.generate_ccd(), expected.generate_ccd());
+    }
+
+    #[test]
+    fn test_flac_audio_tracks() {
+        let paths = get_test_paths();
+        let in_cue = paths.data_plus_audio_flac.join("disc.cue");
+        let cue_sheet = read_to_string(in_cue).unwrap();
+
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.data_plus_audio_flac.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(disc.sector_count, 150);
+        assert_eq!(disc.tracks[1].start, 125);
+        assert_eq!(disc.tracks[1].length, 25);
+
+        let mut img = vec![];
+        disc.write_img(
+            &FileSource::Directory(paths.data_plus_audio_flac.clone()),
+            &mut img,
+        )
+        .unwrap();
+
+        // The FLAC contains 150 sectors' worth of audio from the original
+        // disc, minus 100 samples at the end which are padded with silence.
+        let real_img_path = paths.data_plus_audio_ccd.join("disc.bin");
+        let mut real_img_file = File::open(real_img_path).unwrap();
+        let mut real_img = vec![];
+        real_img_file.read_to_end(&mut real_img).unwrap();
+        let mut expected = real_img[466 * 2352..616 * 2352].to_vec();
+        let padding = expected.len() - 400;
+        expected[padding..].fill(0);
+
+        assert_eq!(expected, img);
+    }
+
+    #[test]
+    fn test_cooked_mode1_img() {
+        let paths = get_test_paths();
+        let in_cue = paths.one_track_cooked.join("basic_image.cue");
+        let cue_sheet = read_to_string(in_cue).unwrap();
+
+        let cuesheet = Cuesheet::parse(&cue_sheet).unwrap();
+        let disc = Disc::from_cuesheet(
+            &cuesheet,
+            &FileSource::Directory(paths.one_track_cooked.clone()),
+        )
+        .unwrap();
+
+        let mut img = vec![];
+        disc.write_img(
+            &FileSource::Directory(paths.one_track_cooked.clone()),
+            &mut img,
+        )
+        .unwrap();
+
+        let real_img_path = paths.one_track_ccd.join("basic_image.img");
+        let mut real_img_file = File::open(real_img_path).unwrap();
+        let mut real_img = vec![];
+        real_img_file.read_to_end(&mut real_img).unwrap();
+
+        assert_eq!(real_img, img);
+
+        let real_ccd_path = paths.one_track_ccd.join("basic_image.ccd");
+        let real_ccd = read_to_string(real_ccd_path).unwrap();
+
+        assert_eq!(real_ccd, disc.generate_ccd());
+    }
 }
@@ -0,0 +1,176 @@
+// LibCrypt is a copy protection used by PAL PlayStation games. It hides a
+// 16-bit key in the Q subchannel: each bit of the key corresponds to a pair
+// of sectors five sectors apart, and a 1 bit is stored by altering the Q
+// subchannel of both sectors in the pair. The game reads the Q subchannel of
+// every sector in the table; if a bit it expects to be set reads back as a
+// normal sector, as it would on a copy made without subchannel data, the
+// protection is triggered.
+//
+// Altered sectors have bits flipped in the minutes of both their relative
+// and absolute times, and a CRC which is the CRC of the unaltered data
+// XORed with 0x8001, so it's always invalid.
+//
+// The original version of LibCrypt stores the key once, in sectors around
+// 03:08-03:35. Later versions add a second copy in sectors around 09:20-09:50.
+//
+// Sector numbers are absolute, as used by LSD and SBI files.
+
+use std::collections::HashMap;
+
+use crate::{crc16, DiscProtection, CRC16_INITIAL_CRC};
+
+/// Sectors storing the first copy of the key, from bit 15 down to bit 0.
+/// Each bit is also stored `PAIR_DISTANCE` sectors later.
+pub const FIRST_COPY: [i64; 16] = [
+    14105, 14231, 14485, 14579, 14649, 14899, 15056, 15130, 15242, 15312, 15378, 15628, 15919,
+    16031, 16101, 16167,
+];
+
+/// Sectors storing the second copy of the key, from bit 15 down to bit 0.
+pub const SECOND_COPY: [i64; 16] = [
+    42045, 42166, 42432, 42580, 42671, 42813, 43012, 43177, 43289, 43354, 43408, 43634, 43963,
+    44054, 44159, 44312,
+];
+
+pub const PAIR_DISTANCE: i64 = 5;
+
+// Finds the bit of the key a sector stores, if any.
+fn key_bit(table: &[i64; 16], absolute_sector: i64) -> Option<u32> {
+    table
+        .iter()
+        .position(|&s| absolute_sector == s || absolute_sector == s + PAIR_DISTANCE)
+        .map(|i| 15 - i as u32)
+}
+
+/// Whether a sector's Q subchannel is altered on a disc with the given
+/// LibCrypt key.
+pub fn is_altered(key: u16, both_copies: bool, absolute_sector: i64) -> bool {
+    let bit = key_bit(&FIRST_COPY, absolute_sector).or(if both_copies {
+        key_bit(&SECOND_COPY, absolute_sector)
+    } else {
+        None
+    });
+
+    bit.is_some_and(|bit| key & (1 << bit) != 0)
+}
+
+/// Alters a correctly generated Q subchannel the way LibCrypt does.
+pub fn alter_q_subchannel(q: &mut [u8]) {
+    let crc = crc16(&q[0..10], CRC16_INITIAL_CRC) ^ 0x8001;
+    // Relative and absolute minutes
+    q[3] ^= 0x04;
+    q[7] ^= 0x20;
+    q[10] = ((crc >> 8) & 0xFF) as u8;
+    q[11] = (crc & 0xFF) as u8;
+}
+
+fn key_from_table(table: &[i64; 16], q_subchannels: &HashMap<i64, Vec<u8>>) -> Option<u16> {
+    let mut key = 0;
+    for (i, &sector) in table.iter().enumerate() {
+        // Both sectors of a pair always store the same bit
+        match (
+            q_subchannels.contains_key(&sector),
+            q_subchannels.contains_key(&(sector + PAIR_DISTANCE)),
+        ) {
+            (true, true) => key |= 1 << (15 - i),
+            (false, false) => (),
+            _ => return None,
+        }
+    }
+
+    Some(key)
+}
+
+/// Checks whether the sectors in an LSD or SBI file match the LibCrypt
+/// layout, returning the matching protection with the disc's key.
+/// Returns None if any sector isn't part of the LibCrypt table, if a bit
+/// is only stored in one of its two sectors, or if the two copies of the
+/// key don't match.
+pub fn detect(q_subchannels: &HashMap<i64, Vec<u8>>) -> Option<DiscProtection> {
+    if q_subchannels.is_empty() {
+        return None;
+    }
+    let known_sector = |sector: i64| {
+        key_bit(&FIRST_COPY, sector).is_some() || key_bit(&SECOND_COPY, sector).is_some()
+    };
+    if !q_subchannels.keys().all(|&sector| known_sector(sector)) {
+        return None;
+    }
+
+    let key = key_from_table(&FIRST_COPY, q_subchannels)?;
+    let second_key = key_from_table(&SECOND_COPY, q_subchannels)?;
+    if second_key == 0 {
+        Some(DiscProtection::LibCryptScheme1 { key })
+    } else if second_key == key {
+        Some(DiscProtection::LibCryptScheme2 { key })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{detect, FIRST_COPY, PAIR_DISTANCE, SECOND_COPY};
+    use crate::sidecar::extract_q_subchannels;
+    use crate::verify::q_crc_is_valid;
+    use crate::{Disc, DiscProtection, TrackMode};
+
+    #[test]
+    fn test_libcrypt_sectors() {
+        let disc = Disc::test_single_track(TrackMode::Mode2Raw, 45000);
+        let protection = Some(DiscProtection::LibCryptScheme2 { key: 0x8001 });
+        let mut sub = vec![];
+        for sector in disc.sectors() {
+            sub.extend(sector.generate_subchannel(&protection, &HashMap::new()));
+        }
+
+        let altered = extract_q_subchannels(&disc, &mut &sub[..]).unwrap();
+        let mut sectors = altered.keys().copied().collect::<Vec<_>>();
+        sectors.sort();
+        assert_eq!(
+            sectors,
+            vec![
+                FIRST_COPY[0],
+                FIRST_COPY[0] + PAIR_DISTANCE,
+                FIRST_COPY[15],
+                FIRST_COPY[15] + PAIR_DISTANCE,
+                SECOND_COPY[0],
+                SECOND_COPY[0] + PAIR_DISTANCE,
+                SECOND_COPY[15],
+                SECOND_COPY[15] + PAIR_DISTANCE,
+            ]
+        );
+
+        // 03:08:05, altered to 07:06:05 and 23:08:05
+        let q = &altered[&FIRST_COPY[0]];
+        assert_eq!(
+            &q[..],
+            &[0x41, 0x01, 0x01, 0x07, 0x06, 0x05, 0x00, 0x23, 0x08, 0x05, 0x38, 0x39]
+        );
+        assert!(!q_crc_is_valid(q));
+
+        assert_eq!(detect(&altered), protection);
+    }
+
+    #[test]
+    fn test_detect_rejects_other_sectors() {
+        let mut q_subchannels = HashMap::new();
+        q_subchannels.insert(FIRST_COPY[3], vec![0; 12]);
+        q_subchannels.insert(FIRST_COPY[3] + PAIR_DISTANCE, vec![0; 12]);
+        assert_eq!(
+            detect(&q_subchannels),
+            Some(DiscProtection::LibCryptScheme1 { key: 0x1000 })
+        );
+
+        // Half of a pair
+        q_subchannels.remove(&(FIRST_COPY[3] + PAIR_DISTANCE));
+        assert_eq!(detect(&q_subchannels), None);
+
+        // A sector outside the table
+        q_subchannels.insert(FIRST_COPY[3] + PAIR_DISTANCE, vec![0; 12]);
+        q_subchannels.insert(20000, vec![0; 12]);
+        assert_eq!(detect(&q_subchannels), None);
+    }
+}
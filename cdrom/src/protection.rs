@@ -0,0 +1,248 @@
+// Works out which copy protection, if any, the sectors in an LSD or SBI file
+// belong to. Each protection leaves its own fingerprint: the number of
+// sectors it alters, where they are, and how their Q subchannel is changed.
+// No single one of these is conclusive on its own, so every protection the
+// data could belong to is returned along with how sure we are.
+
+use std::collections::HashMap;
+
+use crate::verify::q_crc_is_valid;
+use crate::{libcrypt, securom, DiscProtection};
+
+/// How closely an LSD or SBI matches a protection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// Only the number of sectors matches
+    Low,
+    /// The sectors are altered the right way, but not all of them are
+    /// where we'd expect
+    Medium,
+    /// Every sector is where we'd expect, and altered the right way
+    High,
+}
+
+impl std::fmt::Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Confidence::Low => write!(f, "low"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::High => write!(f, "high"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub protection: DiscProtection,
+    pub confidence: Confidence,
+    /// Why the data does or doesn't look like this protection
+    pub reason: String,
+}
+
+fn candidate(protection: DiscProtection, confidence: Confidence, reason: String) -> Candidate {
+    Candidate {
+        protection,
+        confidence,
+        reason,
+    }
+}
+
+fn libcrypt_candidate(q_subchannels: &HashMap<i64, Vec<u8>>, has_crcs: bool) -> Option<Candidate> {
+    let protection = libcrypt::detect(q_subchannels)?;
+    let reason = "every sector is in LibCrypt's table, in pairs five sectors apart";
+    if !has_crcs || q_subchannels.values().all(|q| !q_crc_is_valid(q)) {
+        return Some(candidate(
+            protection,
+            Confidence::High,
+            format!("{}, with bad CRCs", reason),
+        ));
+    }
+
+    Some(candidate(
+        protection,
+        Confidence::Medium,
+        format!("{}, but some have valid CRCs", reason),
+    ))
+}
+
+// SecuROM's sectors can be anywhere on the disc, so only how they've been
+// altered tells the schemes apart.
+fn securom_candidates(q_subchannels: &HashMap<i64, Vec<u8>>, has_crcs: bool) -> Vec<Candidate> {
+    let count = q_subchannels.len();
+    securom::schemes_with_sector_count(count)
+        .into_iter()
+        .map(|protection| {
+            let altered = q_subchannels
+                .iter()
+                .all(|(&sector, q)| securom::looks_altered(&protection, sector, q, has_crcs));
+
+            if altered {
+                candidate(
+                    protection,
+                    Confidence::High,
+                    format!("{} sectors, altered the way this scheme does", count),
+                )
+            } else {
+                candidate(
+                    protection,
+                    Confidence::Low,
+                    format!(
+                        "{} sectors, as used by this scheme, but they aren't altered the way it \
+                         does",
+                        count
+                    ),
+                )
+            }
+        })
+        .collect()
+}
+
+fn is_consecutive(q_subchannels: &HashMap<i64, Vec<u8>>) -> bool {
+    let first = q_subchannels.keys().min().copied().unwrap_or_default();
+    let last = q_subchannels.keys().max().copied().unwrap_or_default();
+    last - first + 1 == q_subchannels.len() as i64
+}
+
+fn discguard_candidate(q_subchannels: &HashMap<i64, Vec<u8>>, has_crcs: bool) -> Option<Candidate> {
+    let crcs_valid = !has_crcs || q_subchannels.values().all(|q| q_crc_is_valid(q));
+    match q_subchannels.len() {
+        // One second of sectors whose relative time reads 29 seconds
+        76 => {
+            let shifted = q_subchannels.values().all(|q| q[4] == 0x29);
+            let confidence = match (crcs_valid && is_consecutive(q_subchannels), shifted) {
+                (true, true) => Confidence::High,
+                (true, false) => Confidence::Medium,
+                _ => Confidence::Low,
+            };
+            let reason = match confidence {
+                Confidence::High => "76 consecutive sectors with their relative time \
+                                     shifted to 29 seconds and valid CRCs"
+                    .to_string(),
+                Confidence::Medium => "76 consecutive sectors with valid CRCs, though their \
+                                       relative times aren't shifted to 29 seconds"
+                    .to_string(),
+                Confidence::Low => "76 sectors, as used by DiscGuard, but they aren't one \
+                                    second of sectors with valid CRCs"
+                    .to_string(),
+            };
+            Some(candidate(
+                DiscProtection::DiscGuardScheme2,
+                confidence,
+                reason,
+            ))
+        }
+        600 => {
+            let (confidence, reason) = if crcs_valid {
+                (Confidence::Medium, "600 sectors with valid CRCs")
+            } else {
+                (
+                    Confidence::Low,
+                    "600 sectors, as used by DiscGuard, but some have bad CRCs",
+                )
+            };
+            Some(candidate(
+                DiscProtection::DiscGuardScheme1,
+                confidence,
+                reason.to_string(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Ranks every protection the Q subchannel data in an LSD or SBI could
+/// belong to, most likely first. `has_crcs` should be false for data read
+/// from an SBI, whose CRCs are regenerated and so say nothing about the
+/// original disc. Returns an empty list if the data doesn't look like any
+/// protection we know of.
+pub fn detect_protection(q_subchannels: &HashMap<i64, Vec<u8>>, has_crcs: bool) -> Vec<Candidate> {
+    if q_subchannels.is_empty() {
+        return vec![];
+    }
+
+    let mut candidates = vec![];
+    candidates.extend(libcrypt_candidate(q_subchannels, has_crcs));
+    candidates.extend(securom_candidates(q_subchannels, has_crcs));
+    candidates.extend(discguard_candidate(q_subchannels, has_crcs));
+    // Stable, so equally likely schemes stay in a predictable order
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.confidence));
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{detect_protection, Confidence};
+    use crate::sidecar::extract_q_subchannels;
+    use crate::{securom, Disc, DiscProtection, TrackMode};
+
+    fn altered_sectors(protection: DiscProtection) -> HashMap<i64, Vec<u8>> {
+        let disc = Disc::test_single_track(TrackMode::Mode1Raw, 45000);
+        let mut sub = vec![];
+        for sector in disc.sectors() {
+            sub.extend(sector.generate_subchannel(&Some(protection), &HashMap::new()));
+        }
+
+        extract_q_subchannels(&disc, &mut &sub[..]).unwrap()
+    }
+
+    #[test]
+    fn test_detect_generated_protections() {
+        for protection in [
+            DiscProtection::DiscGuardScheme2,
+            DiscProtection::LibCryptScheme1 { key: 0x1234 },
+            DiscProtection::LibCryptScheme2 { key: 0xFFFF },
+        ] {
+            let candidates = detect_protection(&altered_sectors(protection), true);
+            assert_eq!(candidates[0].protection, protection);
+            assert_eq!(candidates[0].confidence, Confidence::High);
+            // Anything else which fits is less likely
+            assert!(candidates[1..]
+                .iter()
+                .all(|c| c.confidence < Confidence::High));
+        }
+    }
+
+    #[test]
+    fn test_detect_securom_sectors() {
+        let disc = Disc::test_single_track(TrackMode::Mode1Raw, 45000);
+        for protection in [
+            DiscProtection::SecuROMScheme1,
+            DiscProtection::SecuROMScheme2,
+            DiscProtection::SecuROMScheme3a,
+            DiscProtection::SecuROMScheme3b,
+            DiscProtection::SecuROMScheme3c,
+            DiscProtection::SecuROMScheme4,
+        ] {
+            let count = securom::expected_sector_count(&protection).unwrap();
+            let altered = disc
+                .sectors()
+                .filter(|sector| sector.start % 100 == 50)
+                .take(count)
+                .map(|sector| {
+                    let mut q = sector.generate_q_subchannel_from_layout(&None);
+                    securom::alter_q_subchannel(&protection, &mut q);
+                    (sector.start + 150, q.to_vec())
+                })
+                .collect::<HashMap<_, _>>();
+
+            let candidates = detect_protection(&altered, true);
+            assert_eq!(candidates[0].protection, protection);
+            assert_eq!(candidates[0].confidence, Confidence::High);
+            assert!(candidates[1..]
+                .iter()
+                .all(|c| c.confidence < Confidence::High));
+        }
+    }
+
+    #[test]
+    fn test_detect_unknown_data() {
+        assert!(detect_protection(&HashMap::new(), true).is_empty());
+
+        let mut q_subchannels = HashMap::new();
+        q_subchannels.insert(20000, vec![0; 12]);
+        assert!(detect_protection(&q_subchannels, true).is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+// Most Q subchannel frames are mode 1, and store the current position on the
+// disc. Real discs also interleave a small number of frames in other modes:
+//
+// * Mode 2 frames store the disc's media catalog number (MCN), the same
+//   13-digit UPC/EAN barcode a cuesheet's CATALOG command stores. The Red
+//   Book requires one in at least every 100 frames on discs that have one.
+// * Mode 3 frames store the ISRC of the track they're in, which a cuesheet
+//   stores with the ISRC command. They're only allowed in audio tracks, and
+//   never in a pregap or in the first or last frame of a track.
+//
+// These frames replace the position data entirely, except for the absolute
+// frame number, so players interpolate the position from the frames around
+// them. Some copy checking tools look for the MCN frames, so discs with a
+// catalog number should have them.
+//
+// Sector numbers are absolute, as used by LSD and SBI files.
+
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::{bcd, crc16, CRC16_INITIAL_CRC};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum QModeError {
+    #[error("The catalog number {0:?} isn't 13 digits long")]
+    InvalidCatalog(String),
+
+    #[error("The ISRC {0:?} isn't 5 letters or digits followed by 7 digits")]
+    InvalidIsrc(String),
+}
+
+/// A media catalog number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mcn([u8; 13]);
+
+impl FromStr for Mcn {
+    type Err = QModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .bytes()
+            .map(|b| b.is_ascii_digit().then(|| b - b'0'))
+            .collect::<Option<Vec<u8>>>()
+            .and_then(|digits| <[u8; 13]>::try_from(digits).ok())
+            .ok_or_else(|| QModeError::InvalidCatalog(s.to_string()))?;
+
+        Ok(Mcn(digits))
+    }
+}
+
+impl fmt::Display for Mcn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for digit in self.0 {
+            write!(f, "{}", digit)?;
+        }
+        Ok(())
+    }
+}
+
+/// An International Standard Recording Code, identifying a single recording.
+/// It's made up of a 2-letter country code, a 3-character owner code, a
+/// 2-digit year and a 5-digit serial number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Isrc([u8; 12]);
+
+impl FromStr for Isrc {
+    type Err = QModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || QModeError::InvalidIsrc(s.to_string());
+        let isrc =
+            <[u8; 12]>::try_from(s.to_ascii_uppercase().as_bytes()).map_err(|_| invalid())?;
+        if !isrc[..5].iter().all(u8::is_ascii_alphanumeric)
+            || !isrc[5..].iter().all(u8::is_ascii_digit)
+        {
+            return Err(invalid());
+        }
+
+        Ok(Isrc(isrc))
+    }
+}
+
+impl fmt::Display for Isrc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only ever contains ASCII
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+/// Where mode 2 frames are placed. Mode 3 frames go halfway between them,
+/// so the two never collide.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// One frame in every 100, on every absolute frame number that's a
+    /// multiple of 100. This is the least often the Red Book allows, and
+    /// is what most dumps of pressed discs show.
+    #[default]
+    Standard,
+    /// Every nth frame, on every absolute frame number that's a multiple
+    /// of n. There's no room for mode 3 frames if n is 1.
+    EveryNth(u32),
+}
+
+impl Placement {
+    fn interval(&self) -> i64 {
+        match self {
+            Placement::Standard => 100,
+            Placement::EveryNth(n) => *n as i64,
+        }
+    }
+
+    /// Whether the sector at the given absolute position gets a mode 2 frame.
+    pub fn includes_mcn(&self, absolute_sector: i64) -> bool {
+        let interval = self.interval();
+        interval > 0 && absolute_sector % interval == 0
+    }
+
+    /// Whether the sector at the given absolute position gets a mode 3 frame.
+    pub fn includes_isrc(&self, absolute_sector: i64) -> bool {
+        let interval = self.interval();
+        interval > 1 && absolute_sector % interval == interval / 2
+    }
+}
+
+/// Generates a mode 2 Q subchannel frame. `control` is the control field,
+/// which is the same as in the surrounding mode 1 frames.
+pub fn mcn_q_subchannel(mcn: &Mcn, control: u8, absolute_sector: i64) -> [u8; 12] {
+    let mut q = [0; 12];
+    q[0] = (control << 4) | 2;
+    // The 13 digits are packed two to a byte, leaving the low
+    // half of the last byte empty.
+    for (i, digit) in mcn.0.iter().enumerate() {
+        q[1 + i / 2] |= if i % 2 == 0 { digit << 4 } else { *digit };
+    }
+    // Byte 8 is always zero, and byte 9 is the frame part of the absolute
+    // time; the minutes and seconds aren't stored.
+    q[9] = bcd(absolute_sector % 75);
+    let crc = crc16(&q[0..10], CRC16_INITIAL_CRC);
+    q[10] = ((crc >> 8) & 0xFF) as u8;
+    q[11] = (crc & 0xFF) as u8;
+
+    q
+}
+
+// ISRCs use a 6-bit character set for the letters, in which
+// the digits are 0-9 and the letters start at 17.
+fn isrc_character(c: u8) -> u8 {
+    if c.is_ascii_digit() {
+        c - b'0'
+    } else {
+        c - b'A' + 17
+    }
+}
+
+/// Generates a mode 3 Q subchannel frame. `control` is the control field,
+/// which is the same as in the surrounding mode 1 frames.
+pub fn isrc_q_subchannel(isrc: &Isrc, control: u8, absolute_sector: i64) -> [u8; 12] {
+    let mut q = [0; 12];
+    q[0] = (control << 4) | 3;
+    // The first five characters are packed into 30 bits, followed by two
+    // zero bits.
+    let packed = isrc.0[..5]
+        .iter()
+        .fold(0u32, |packed, &c| (packed << 6) | isrc_character(c) as u32)
+        << 2;
+    q[1..5].copy_from_slice(&packed.to_be_bytes());
+    // The last seven are digits, packed two to a byte like the MCN.
+    for (i, digit) in isrc.0[5..].iter().enumerate() {
+        let digit = digit - b'0';
+        q[5 + i / 2] |= if i % 2 == 0 { digit << 4 } else { digit };
+    }
+    q[9] = bcd(absolute_sector % 75);
+    let crc = crc16(&q[0..10], CRC16_INITIAL_CRC);
+    q[10] = ((crc >> 8) & 0xFF) as u8;
+    q[11] = (crc & 0xFF) as u8;
+
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{isrc_q_subchannel, mcn_q_subchannel, Isrc, Mcn, Placement, QModeError};
+    use crate::verify::q_crc_is_valid;
+
+    #[test]
+    fn test_parse_mcn() {
+        let mcn: Mcn = "0724384260927".parse().unwrap();
+        assert_eq!(mcn.to_string(), "0724384260927");
+
+        assert_eq!(
+            "12345".parse::<Mcn>(),
+            Err(QModeError::InvalidCatalog("12345".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_mcn_frames() {
+        let mcn: Mcn = "0724384260927".parse().unwrap();
+        // 00:02:01
+        let q = mcn_q_subchannel(&mcn, 0, 151);
+        assert_eq!(
+            &q[0..10],
+            &[0x02, 0x07, 0x24, 0x38, 0x42, 0x60, 0x92, 0x70, 0, 0x01]
+        );
+        assert!(q_crc_is_valid(&q));
+
+        assert!(Placement::Standard.includes_mcn(200));
+        assert!(!Placement::Standard.includes_mcn(250));
+        assert!(Placement::EveryNth(50).includes_mcn(250));
+        assert!(!Placement::EveryNth(0).includes_mcn(0));
+    }
+
+    #[test]
+    fn test_isrc_frames() {
+        let isrc: Isrc = "usabc1234567".parse().unwrap();
+        assert_eq!(isrc.to_string(), "USABC1234567");
+        assert_eq!(
+            "US-ABC-12-34567".parse::<Isrc>(),
+            Err(QModeError::InvalidIsrc("US-ABC-12-34567".to_string()))
+        );
+
+        // U=37, S=35, A=17, B=18, C=19
+        let q = isrc_q_subchannel(&isrc, 0, 160);
+        assert_eq!(
+            &q[0..10],
+            &[0x03, 0x96, 0x34, 0x52, 0x4C, 0x12, 0x34, 0x56, 0x70, 0x10]
+        );
+        assert!(q_crc_is_valid(&q));
+
+        assert!(Placement::Standard.includes_isrc(250));
+        assert!(!Placement::Standard.includes_isrc(200));
+        assert!(!Placement::EveryNth(1).includes_isrc(0));
+    }
+}
@@ -0,0 +1,288 @@
+// "Cooked" disc images only contain the user data portion of each sector,
+// dropping the sync pattern, header and error correction data that are
+// present on the disc itself. CloneCD images are always raw, so we need to
+// rebuild all of those fields ourselves.
+//
+// The layout of each sector type, as well as the EDC and ECC algorithms,
+// are described in ECMA-130, sections 14 and 16 and annex A.
+// http://www.ecma-international.org/publications/standards/Ecma-130.htm
+
+use crate::bcd;
+
+/// Size of a raw sector, in bytes.
+pub const RAW_SECTOR_SIZE: usize = 2352;
+
+// Every data sector starts with this 12-byte sync pattern.
+const SYNC: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+
+// Lookup tables for the ECC (a Reed-Solomon product code over GF(2^8)) and
+// for the EDC (a CRC32 using the polynomial
+// (x^16 + x^15 + x^2 + 1) * (x^16 + x^2 + x + 1)).
+struct Tables {
+    ecc_f: [u8; 256],
+    ecc_b: [u8; 256],
+    edc: [u32; 256],
+}
+
+const TABLES: Tables = build_tables();
+
+const fn build_tables() -> Tables {
+    let mut ecc_f = [0; 256];
+    let mut ecc_b = [0; 256];
+    let mut edc = [0; 256];
+
+    let mut i = 0;
+    while i < 256 {
+        let j = (i << 1) ^ if i & 0x80 != 0 { 0x11D } else { 0 };
+        ecc_f[i] = j as u8;
+        ecc_b[i ^ j] = i as u8;
+
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = (crc >> 1) ^ if crc & 1 != 0 { 0xD8018001 } else { 0 };
+            bit += 1;
+        }
+        edc[i] = crc;
+
+        i += 1;
+    }
+
+    Tables { ecc_f, ecc_b, edc }
+}
+
+/// Calculates the error detection code for the provided bytes.
+pub fn edc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, &byte| {
+        (crc >> 8) ^ TABLES.edc[((crc ^ byte as u32) & 0xFF) as usize]
+    })
+}
+
+// Calculates one set of parity bytes. The P and Q parity use the same
+// algorithm, but read the source bytes in a different order:
+// P parity reads down 86 columns of 24 bytes, while Q parity reads
+// along 52 diagonals of 43 bytes.
+fn ecc_block(
+    src: &[u8],
+    major_count: usize,
+    minor_count: usize,
+    major_mult: usize,
+    minor_inc: usize,
+    dest: &mut [u8],
+) {
+    let size = major_count * minor_count;
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_mult + (major & 1);
+        let mut ecc_a = 0;
+        let mut ecc_b = 0;
+        for _ in 0..minor_count {
+            let temp = src[index];
+            index += minor_inc;
+            if index >= size {
+                index -= size;
+            }
+            ecc_a ^= temp;
+            ecc_b ^= temp;
+            ecc_a = TABLES.ecc_f[ecc_a as usize];
+        }
+        ecc_a = TABLES.ecc_b[(TABLES.ecc_f[ecc_a as usize] ^ ecc_b) as usize];
+        dest[major] = ecc_a;
+        dest[major + major_count] = ecc_a ^ ecc_b;
+    }
+}
+
+// Fills in the 172 bytes of P parity at 0x81C and the 104 bytes of
+// Q parity at 0x8C8. Both are calculated over everything from the header
+// onward; Q parity also covers the P parity bytes.
+// Mode 2 sectors calculate the ECC as though the header was all zeroes,
+// so that the header can be rewritten without invalidating it.
+fn generate_ecc(sector: &mut [u8; RAW_SECTOR_SIZE], zero_address: bool) {
+    let mut header = [0; 4];
+    if zero_address {
+        header.copy_from_slice(&sector[12..16]);
+        sector[12..16].fill(0);
+    }
+
+    let (src, dest) = sector.split_at_mut(0x81C);
+    ecc_block(&src[0xC..], 86, 24, 2, 86, &mut dest[..172]);
+    let (src, dest) = sector.split_at_mut(0x8C8);
+    ecc_block(&src[0xC..], 52, 43, 86, 88, &mut dest[..104]);
+
+    if zero_address {
+        sector[12..16].copy_from_slice(&header);
+    }
+}
+
+fn write_header(sector: &mut [u8; RAW_SECTOR_SIZE], absolute_sector: i64, mode: u8) {
+    sector[0..12].copy_from_slice(&SYNC);
+    sector[12] = bcd(absolute_sector / 4500);
+    sector[13] = bcd((absolute_sector / 75) % 60);
+    sector[14] = bcd(absolute_sector % 75);
+    sector[15] = mode;
+}
+
+/// Builds a raw mode 1 sector from 2048 bytes of user data.
+/// `absolute_sector` is the sector's position relative to the start of
+/// the disc, including the 150-sector lead-in, and is stored in the header.
+pub fn mode1_sector(absolute_sector: i64, data: &[u8]) -> [u8; RAW_SECTOR_SIZE] {
+    let mut sector = [0; RAW_SECTOR_SIZE];
+    write_header(&mut sector, absolute_sector, 1);
+    sector[16..2064].copy_from_slice(&data[..2048]);
+
+    let crc = edc(&sector[0..2064]);
+    sector[2064..2068].copy_from_slice(&crc.to_le_bytes());
+    // Bytes 2068-2075 are reserved and always zero.
+    generate_ecc(&mut sector, false);
+
+    sector
+}
+
+// CD-ROM XA mode 2 sectors begin with an 8-byte subheader, which is made
+// up of the same four bytes repeated twice: file number, channel number,
+// submode and coding information. Bit 5 of the submode selects between
+// form 1 (2048 bytes of data with EDC and ECC) and form 2 (2324 bytes of
+// data with only an EDC).
+const SUBMODE_FORM2: u8 = 1 << 5;
+const SUBMODE_DATA: u8 = 1 << 3;
+
+/// Subheader used for MODE2/2048 tracks, which don't store one.
+/// This describes a plain form 1 data sector.
+pub const FORM1_SUBHEADER: [u8; 8] = [0, 0, SUBMODE_DATA, 0, 0, 0, SUBMODE_DATA, 0];
+/// Subheader used for MODE2/2324 tracks, which don't store one.
+/// This describes a form 2 sector with no special contents.
+pub const FORM2_SUBHEADER: [u8; 8] = [0, 0, SUBMODE_FORM2, 0, 0, 0, SUBMODE_FORM2, 0];
+
+/// Builds a raw mode 2 form 1 sector from an 8-byte XA subheader and
+/// 2048 bytes of user data.
+pub fn mode2_form1_sector(
+    absolute_sector: i64,
+    subheader: &[u8],
+    data: &[u8],
+) -> [u8; RAW_SECTOR_SIZE] {
+    let mut sector = [0; RAW_SECTOR_SIZE];
+    write_header(&mut sector, absolute_sector, 2);
+    sector[16..24].copy_from_slice(&subheader[..8]);
+    sector[24..2072].copy_from_slice(&data[..2048]);
+
+    // Unlike mode 1, the EDC doesn't cover the sync pattern and header.
+    let crc = edc(&sector[16..2072]);
+    sector[2072..2076].copy_from_slice(&crc.to_le_bytes());
+    generate_ecc(&mut sector, true);
+
+    sector
+}
+
+/// Builds a raw mode 2 form 2 sector from an 8-byte XA subheader and
+/// 2324 bytes of user data.
+pub fn mode2_form2_sector(
+    absolute_sector: i64,
+    subheader: &[u8],
+    data: &[u8],
+) -> [u8; RAW_SECTOR_SIZE] {
+    let mut sector = [0; RAW_SECTOR_SIZE];
+    write_header(&mut sector, absolute_sector, 2);
+    sector[16..24].copy_from_slice(&subheader[..8]);
+    sector[24..2348].copy_from_slice(&data[..2324]);
+
+    let crc = edc(&sector[16..2348]);
+    sector[2348..2352].copy_from_slice(&crc.to_le_bytes());
+
+    sector
+}
+
+fn is_form2(subheader: &[u8]) -> bool {
+    subheader[2] & SUBMODE_FORM2 != 0
+}
+
+/// Builds a raw mode 2 sector from the 2336 bytes following the header,
+/// as stored in MODE2/2336 tracks. For XA sectors, the EDC and ECC are
+/// recalculated based on the sector's form.
+pub fn mode2_sector(absolute_sector: i64, data: &[u8]) -> [u8; RAW_SECTOR_SIZE] {
+    let (subheader, payload) = data[..2336].split_at(8);
+
+    // Mode 2 sectors which aren't XA sectors don't have a subheader
+    // or any error correction; all 2336 bytes are user data. XA sectors
+    // always repeat their subheader, so use that to tell them apart.
+    if subheader[0..4] != subheader[4..8] {
+        let mut sector = [0; RAW_SECTOR_SIZE];
+        write_header(&mut sector, absolute_sector, 2);
+        sector[16..2352].copy_from_slice(&data[..2336]);
+        return sector;
+    }
+
+    if is_form2(subheader) {
+        let mut sector = mode2_form2_sector(absolute_sector, subheader, payload);
+        // The form 2 EDC is optional, and some discs leave it zeroed out;
+        // preserve that rather than filling it in.
+        if payload[2324..2328] == [0; 4] {
+            sector[2348..2352].fill(0);
+        }
+        sector
+    } else {
+        mode2_form1_sector(absolute_sector, subheader, payload)
+    }
+}
+
+/// Builds a raw mode 2 sector from a MODE2/2332 track, which stores the
+/// 8-byte subheader and 2324 bytes of data but leaves out the EDC.
+/// Form 1 sectors only use the first 2048 bytes of the data.
+pub fn mode2_mixed_sector(absolute_sector: i64, data: &[u8]) -> [u8; RAW_SECTOR_SIZE] {
+    let (subheader, payload) = data[..2332].split_at(8);
+    if is_form2(subheader) {
+        mode2_form2_sector(absolute_sector, subheader, payload)
+    } else {
+        mode2_form1_sector(absolute_sector, subheader, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read;
+    use std::path::PathBuf;
+
+    use super::{
+        mode1_sector, mode2_form1_sector, mode2_form2_sector, mode2_mixed_sector, mode2_sector,
+        FORM1_SUBHEADER, FORM2_SUBHEADER, RAW_SECTOR_SIZE,
+    };
+
+    #[test]
+    fn test_mode1_sector_matches_real_disc() {
+        let bin = read(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("..")
+                .join("testdata")
+                .join("onetrack")
+                .join("bincue")
+                .join("basic_image.bin"),
+        )
+        .unwrap();
+
+        for (lba, raw) in bin.chunks(RAW_SECTOR_SIZE).enumerate() {
+            let rebuilt = mode1_sector(lba as i64 + 150, &raw[16..2064]);
+            assert_eq!(raw, &rebuilt[..], "sector {} differs", lba);
+        }
+    }
+
+    #[test]
+    fn test_mode2_sector_round_trip() {
+        let data = (0..2324).map(|i| (i * 7) as u8).collect::<Vec<u8>>();
+
+        // Form 1 and form 2 sectors built from cooked data should come back
+        // unchanged when rebuilt from their MODE2/2336 and MODE2/2332 forms.
+        let form1 = mode2_form1_sector(1000, &FORM1_SUBHEADER, &data);
+        assert_eq!(form1, mode2_sector(1000, &form1[16..]));
+        assert_eq!(form1, mode2_mixed_sector(1000, &form1[16..2348]));
+        assert_eq!(&form1[12..16], &[0x00, 0x13, 0x25, 0x02]);
+
+        let form2 = mode2_form2_sector(1000, &FORM2_SUBHEADER, &data);
+        assert_eq!(form2, mode2_sector(1000, &form2[16..]));
+        assert_eq!(form2, mode2_mixed_sector(1000, &form2[16..2348]));
+
+        // The optional form 2 EDC is left empty if the source didn't have one.
+        let mut no_edc = form2;
+        no_edc[2348..].fill(0);
+        assert_eq!(no_edc, mode2_sector(1000, &no_edc[16..]));
+    }
+}
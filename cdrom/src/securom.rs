@@ -0,0 +1,211 @@
+// SecuROM is a copy protection used by PC games. Among other checks, it
+// deliberately corrupts the Q subchannel of a set of sectors when the disc is
+// mastered, and checks that those sectors still read back as corrupted.
+// Copies made without subchannel data, or with subchannel data regenerated
+// from the disc's layout, fail the check.
+//
+// There are several versions of the protection, which differ in how many
+// sectors are corrupted and how. The naming follows DiscImageCreator:
+//
+// Scheme 1  - "Type_1", SecuROM 4.6x-4.7x: 216 sectors, in groups of three,
+//             with broken CRCs.
+// Scheme 2  - "Type_2", SecuROM 4.8x and older: 90 sectors with broken CRCs.
+// Scheme 3a - "Type_3_1", SecuROM 7.x: 99 sectors with broken CRCs.
+// Scheme 3b - "Type_3_2", SecuROM 7.x: 99 sectors whose absolute time is
+//             one frame behind, with a valid CRC.
+// Scheme 3c - "Type_3_3", SecuROM 7.x: 99 sectors whose relative time is
+//             one frame ahead, with a valid CRC.
+// Scheme 4  - "Type_4": 10 sectors with broken CRCs.
+//
+// Where the corrupted sectors are differs from disc to disc, and there's no
+// documented way to work it out from the rest of the disc, so they can't
+// be generated; they only ever come from an LSD or SBI dumped from the
+// original disc. What's here is used to recognise that data.
+//
+// Sector numbers are absolute, as used by LSD and SBI files.
+
+use crate::verify::q_crc_is_valid;
+use crate::{bcd, crc16, DiscProtection, CRC16_INITIAL_CRC};
+
+enum Alteration {
+    BrokenCrc,
+    AbsoluteTimeBehind,
+    RelativeTimeAhead,
+}
+
+struct Layout {
+    sector_count: usize,
+    alteration: Alteration,
+}
+
+fn layout(protection: &DiscProtection) -> Option<Layout> {
+    let (sector_count, alteration) = match protection {
+        DiscProtection::SecuROMScheme1 => (216, Alteration::BrokenCrc),
+        DiscProtection::SecuROMScheme2 => (90, Alteration::BrokenCrc),
+        DiscProtection::SecuROMScheme3a => (99, Alteration::BrokenCrc),
+        DiscProtection::SecuROMScheme3b => (99, Alteration::AbsoluteTimeBehind),
+        DiscProtection::SecuROMScheme3c => (99, Alteration::RelativeTimeAhead),
+        DiscProtection::SecuROMScheme4 => (10, Alteration::BrokenCrc),
+        _ => return None,
+    };
+
+    Some(Layout {
+        sector_count,
+        alteration,
+    })
+}
+
+/// The number of sectors with corrupted Q subchannel data on a disc
+/// protected with the given SecuROM scheme.
+pub fn expected_sector_count(protection: &DiscProtection) -> Option<usize> {
+    layout(protection).map(|layout| layout.sector_count)
+}
+
+/// Every SecuROM scheme which corrupts the given number of sectors.
+pub fn schemes_with_sector_count(count: usize) -> Vec<DiscProtection> {
+    [
+        DiscProtection::SecuROMScheme1,
+        DiscProtection::SecuROMScheme2,
+        DiscProtection::SecuROMScheme3a,
+        DiscProtection::SecuROMScheme3b,
+        DiscProtection::SecuROMScheme3c,
+        DiscProtection::SecuROMScheme4,
+    ]
+    .into_iter()
+    .filter(|scheme| expected_sector_count(scheme) == Some(count))
+    .collect()
+}
+
+fn from_bcd(value: u8) -> i64 {
+    ((value >> 4) * 10 + (value & 0x0F)) as i64
+}
+
+// Moves an MSF time stored in BCD at `at` by `frames`.
+fn shift_time(q: &mut [u8], at: usize, frames: i64) {
+    let sector = from_bcd(q[at]) * 4500 + from_bcd(q[at + 1]) * 75 + from_bcd(q[at + 2]) + frames;
+    let sector = sector.max(0);
+    q[at] = bcd(sector / 4500);
+    q[at + 1] = bcd((sector / 75) % 60);
+    q[at + 2] = bcd(sector % 75);
+}
+
+/// Corrupts a correctly generated Q subchannel the way the given
+/// scheme does.
+pub fn alter_q_subchannel(protection: &DiscProtection, q: &mut [u8]) {
+    let Some(layout) = layout(protection) else {
+        return;
+    };
+
+    match layout.alteration {
+        Alteration::BrokenCrc => {
+            q[11] ^= 0x80;
+            return;
+        }
+        Alteration::AbsoluteTimeBehind => shift_time(q, 7, -1),
+        Alteration::RelativeTimeAhead => shift_time(q, 3, 1),
+    }
+    let crc = crc16(&q[0..10], CRC16_INITIAL_CRC);
+    q[10] = ((crc >> 8) & 0xFF) as u8;
+    q[11] = (crc & 0xFF) as u8;
+}
+
+/// Whether a sector's Q subchannel looks like it was corrupted the way the
+/// given scheme does, regardless of where the sector is. If `has_crc` is
+/// false, as for entries read from an SBI, the CRC isn't checked.
+pub fn looks_altered(
+    protection: &DiscProtection,
+    absolute_sector: i64,
+    q: &[u8],
+    has_crc: bool,
+) -> bool {
+    let Some(layout) = layout(protection) else {
+        return false;
+    };
+    let absolute = from_bcd(q[7]) * 4500 + from_bcd(q[8]) * 75 + from_bcd(q[9]);
+    let crc_is = |valid: bool| !has_crc || q_crc_is_valid(q) == valid;
+
+    match layout.alteration {
+        Alteration::BrokenCrc => crc_is(false) && absolute == absolute_sector,
+        Alteration::AbsoluteTimeBehind => crc_is(true) && absolute == absolute_sector - 1,
+        Alteration::RelativeTimeAhead => crc_is(true) && absolute == absolute_sector,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        alter_q_subchannel, expected_sector_count, looks_altered, schemes_with_sector_count,
+    };
+    use crate::verify::{q_crc_is_valid, QPosition};
+    use crate::{Disc, DiscProtection, TrackMode};
+
+    fn altered_sector(protection: DiscProtection, absolute_sector: i64) -> Vec<u8> {
+        let disc = Disc::test_single_track(TrackMode::Mode1Raw, 20000);
+        let sector = disc
+            .sectors()
+            .find(|sector| sector.start + 150 == absolute_sector)
+            .unwrap();
+        let mut q = sector.generate_q_subchannel_from_layout(&None).to_vec();
+        alter_q_subchannel(&protection, &mut q);
+        q
+    }
+
+    #[test]
+    fn test_securom_sector_counts() {
+        for protection in [
+            DiscProtection::SecuROMScheme1,
+            DiscProtection::SecuROMScheme2,
+            DiscProtection::SecuROMScheme3a,
+            DiscProtection::SecuROMScheme3b,
+            DiscProtection::SecuROMScheme3c,
+            DiscProtection::SecuROMScheme4,
+        ] {
+            let count = expected_sector_count(&protection).unwrap();
+            assert!(schemes_with_sector_count(count).contains(&protection));
+        }
+        assert_eq!(
+            expected_sector_count(&DiscProtection::DiscGuardScheme2),
+            None
+        );
+    }
+
+    #[test]
+    fn test_securom_alterations() {
+        let q = altered_sector(DiscProtection::SecuROMScheme1, 3152);
+        assert!(!q_crc_is_valid(&q));
+        // Corrupted in the CRC only
+        assert_eq!(QPosition::decode(&q).absolute.to_string(), "00:42:02");
+        assert!(looks_altered(
+            &DiscProtection::SecuROMScheme1,
+            3152,
+            &q,
+            true
+        ));
+
+        let q = altered_sector(DiscProtection::SecuROMScheme3b, 3225);
+        assert!(q_crc_is_valid(&q));
+        assert_eq!(QPosition::decode(&q).absolute.to_string(), "00:42:74");
+        assert!(looks_altered(
+            &DiscProtection::SecuROMScheme3b,
+            3225,
+            &q,
+            true
+        ));
+        assert!(!looks_altered(
+            &DiscProtection::SecuROMScheme3a,
+            3225,
+            &q,
+            true
+        ));
+
+        let q = altered_sector(DiscProtection::SecuROMScheme3c, 3225);
+        assert!(q_crc_is_valid(&q));
+        assert_eq!(QPosition::decode(&q).relative.to_string(), "00:41:01");
+        assert!(looks_altered(
+            &DiscProtection::SecuROMScheme3c,
+            3225,
+            &q,
+            true
+        ));
+    }
+}
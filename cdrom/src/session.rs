@@ -0,0 +1,251 @@
+// Discs can be written in more than one session, each with its own lead-in,
+// table of contents and lead-out. Most multisession discs seen in the wild
+// are Enhanced CDs (also known as CD-Extra or Blue Book discs), which have
+// audio tracks in the first session and a data track in the second, so
+// that audio players which only read the first session never try to play
+// the data track.
+//
+// Disc images don't contain the lead-out and lead-in between sessions, but
+// positions on the disc still count them, so every session after the first
+// starts some distance further along the disc than its position in the
+// image. Cuesheets mark the start of each session with `REM SESSION nn`, and
+// can give the lengths of the areas between them with `REM LEAD-OUT`,
+// `REM LEAD-IN` and `REM PREGAP`; otherwise the lengths used by the Blue Book
+// are assumed.
+
+use thiserror::Error;
+
+use crate::cuesheet::Cuesheet;
+
+/// The length of the first session's lead-out: 1:30.
+pub const FIRST_LEAD_OUT: i64 = 6750;
+/// The length of every later session's lead-out: 0:30.
+pub const LEAD_OUT: i64 = 2250;
+/// The length of the lead-in of every session after the first: 1:00.
+pub const LEAD_IN: i64 = 4500;
+/// The length of the pregap of the first track in a session: 0:02.
+pub const PREGAP: i64 = 150;
+
+/// The latest the last session's lead-out can start, as written in B0 TOC
+/// entries: the end of an 80-minute disc.
+pub const MAX_LEAD_OUT: (i64, i64, i64) = (79, 59, 74);
+/// The start of the first session's lead-in, as written in the C0 TOC
+/// entry. This depends on the disc and isn't in any cuesheet, so this is
+/// just a typical value.
+pub const FIRST_LEAD_IN: (i64, i64, i64) = (97, 27, 0);
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SessionError {
+    #[error("{0:?} isn't a valid session number")]
+    InvalidNumber(String),
+
+    #[error("Session {found} follows session {previous}")]
+    OutOfOrder { previous: u8, found: u8 },
+
+    #[error("Session {0} doesn't contain any tracks")]
+    Empty(u8),
+
+    #[error("REM {command} has an invalid time: {value:?}")]
+    InvalidTime { command: String, value: String },
+}
+
+/// A session after the first one on a multisession disc.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub number: u8,
+    /// The number of the session's first track
+    pub first_track: u8,
+    /// The number of sectors on the disc between the end of the previous
+    /// session's last track and the start of this session's first track
+    /// in the image; these sectors aren't in the image.
+    pub gap: i64,
+}
+
+// Lengths read from the cuesheet, before the defaults are filled in
+#[derive(Default)]
+struct SessionLengths {
+    number: u8,
+    first_track: u8,
+    lead_out: Option<i64>,
+    lead_in: Option<i64>,
+    pregap: Option<i64>,
+}
+
+fn parse_time(command: &str, value: &str) -> Result<i64, SessionError> {
+    let invalid = || SessionError::InvalidTime {
+        command: command.to_string(),
+        value: value.to_string(),
+    };
+    let parts = value
+        .split(':')
+        .map(|part| part.parse::<i64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts[..] {
+        [m, s, f] if s < 60 && f < 75 => Ok(m * 4500 + s * 75 + f),
+        _ => Err(invalid()),
+    }
+}
+
+/// Reads the sessions after the first from a cuesheet's REM commands. The
+/// result is empty for single-session discs.
+// Tracks are counted the same way Disc::from_cuesheet numbers them.
+pub fn sessions_from_cuesheet(cuesheet: &Cuesheet) -> Result<Vec<Session>, SessionError> {
+    let mut sessions = vec![SessionLengths {
+        number: 1,
+        first_track: 1,
+        ..Default::default()
+    }];
+    let tracks = cuesheet.tracks.len() as u8;
+
+    // Each REM comment, along with how many tracks came before it
+    let rems = cuesheet.rems.iter().map(|rem| (0, rem)).chain(
+        cuesheet
+            .tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(i, track)| track.rems.iter().map(move |rem| (i as u8 + 1, rem))),
+    );
+    for (tracks_so_far, rem) in rems {
+        let current = sessions.last_mut().unwrap();
+        let value = rem.value.as_str();
+        match rem.name.to_ascii_uppercase().as_str() {
+            "SESSION" => {
+                let number = value
+                    .parse::<u8>()
+                    .map_err(|_| SessionError::InvalidNumber(value.to_string()))?;
+                // Cuesheets usually mark the first session too
+                if number == 1 && current.number == 1 && tracks_so_far == 0 {
+                    continue;
+                }
+                if number != current.number + 1 {
+                    return Err(SessionError::OutOfOrder {
+                        previous: current.number,
+                        found: number,
+                    });
+                }
+                if current.first_track > tracks_so_far {
+                    return Err(SessionError::Empty(current.number));
+                }
+                sessions.push(SessionLengths {
+                    number,
+                    first_track: tracks_so_far + 1,
+                    ..Default::default()
+                });
+            }
+            "LEAD-OUT" => current.lead_out = Some(parse_time(&rem.name, value)?),
+            "LEAD-IN" => current.lead_in = Some(parse_time(&rem.name, value)?),
+            "PREGAP" => current.pregap = Some(parse_time(&rem.name, value)?),
+            _ => (),
+        }
+    }
+    let last = sessions.last().unwrap();
+    if last.first_track > tracks {
+        return Err(SessionError::Empty(last.number));
+    }
+
+    Ok(sessions
+        .windows(2)
+        .map(|pair| {
+            let (previous, session) = (&pair[0], &pair[1]);
+            let lead_out = previous.lead_out.unwrap_or(if previous.number == 1 {
+                FIRST_LEAD_OUT
+            } else {
+                LEAD_OUT
+            });
+            // If the first track has an INDEX 00 or a PREGAP command, its
+            // pregap is already in the image.
+            let first_track = &cuesheet.tracks[session.first_track as usize - 1];
+            let pregap = if first_track.index(0).is_some() || first_track.pregap.is_some() {
+                0
+            } else {
+                session.pregap.unwrap_or(PREGAP)
+            };
+
+            Session {
+                number: session.number,
+                first_track: session.first_track,
+                gap: lead_out + session.lead_in.unwrap_or(LEAD_IN) + pregap,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Session, SessionError};
+    use crate::cuesheet::Cuesheet;
+
+    fn sessions_from_cuesheet(cue_sheet: &str) -> Result<Vec<Session>, SessionError> {
+        super::sessions_from_cuesheet(&Cuesheet::parse(cue_sheet).unwrap())
+    }
+
+    const ENHANCED_CD: &str = r#"REM SESSION 01
+FILE "disc (Track 1).bin" BINARY
+  TRACK 01 AUDIO
+    INDEX 01 00:00:00
+FILE "disc (Track 2).bin" BINARY
+  TRACK 02 AUDIO
+    INDEX 00 00:00:00
+    INDEX 01 00:02:00
+REM LEAD-OUT 01:30:00
+REM SESSION 02
+REM LEAD-IN 01:00:00
+REM PREGAP 00:02:00
+FILE "disc (Track 3).bin" BINARY
+  TRACK 03 MODE2/2352
+    INDEX 01 00:00:00
+"#;
+
+    #[test]
+    fn test_sessions_from_cuesheet() {
+        assert_eq!(
+            sessions_from_cuesheet(ENHANCED_CD),
+            Ok(vec![Session {
+                number: 2,
+                first_track: 3,
+                gap: 11400,
+            }])
+        );
+
+        // The same lengths are assumed without the REM commands, and a
+        // pregap in the image isn't counted again.
+        let cue = ENHANCED_CD
+            .replace("REM LEAD-OUT 01:30:00\n", "")
+            .replace("REM LEAD-IN 01:00:00\nREM PREGAP 00:02:00\n", "")
+            .replace(
+                "TRACK 03 MODE2/2352\n    INDEX 01 00:00:00",
+                "TRACK 03 MODE2/2352\n    INDEX 00 00:00:00\n    INDEX 01 00:02:00",
+            );
+        assert_eq!(sessions_from_cuesheet(&cue).unwrap()[0].gap, 11250);
+        let cue = ENHANCED_CD.replace(
+            "TRACK 03 MODE2/2352\n",
+            "TRACK 03 MODE2/2352\n    PREGAP 00:02:00\n",
+        );
+        assert_eq!(sessions_from_cuesheet(&cue).unwrap()[0].gap, 11250);
+
+        let single = ENHANCED_CD.replace("REM SESSION 02\n", "");
+        assert_eq!(sessions_from_cuesheet(&single), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_session_errors() {
+        assert_eq!(
+            sessions_from_cuesheet(&ENHANCED_CD.replace("SESSION 02", "SESSION 03")),
+            Err(SessionError::OutOfOrder {
+                previous: 1,
+                found: 3,
+            })
+        );
+        assert_eq!(
+            sessions_from_cuesheet(&format!("{}REM SESSION 03\n", ENHANCED_CD)),
+            Err(SessionError::Empty(3))
+        );
+        assert_eq!(
+            sessions_from_cuesheet(&ENHANCED_CD.replace("01:30:00", "01:90:00")),
+            Err(SessionError::InvalidTime {
+                command: "LEAD-OUT".to_string(),
+                value: "01:90:00".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,455 @@
+// LSD and SBI files are sidecar files which sit next to a cuesheet and
+// contain the Q subchannel data for sectors whose Q subchannel can't be
+// generated from the disc's layout, such as those deliberately altered by
+// copy protection. Programs (mainly just PS1 emulators so far) that make use
+// of them simply check if there's an LSD or SBI file with the same basename
+// next to the .cue; they've never been referenced by the cuesheet itself.
+//
+// Both formats key their entries by the absolute MSF of the sector, in BCD.
+// Entries are returned in, and written from, a map from absolute sector
+// numbers to the 12 bytes of Q subchannel data, which is the same form
+// `Sector::generate_subchannel` accepts.
+//
+// Neither format has any redundancy, so a damaged file is easy to misread.
+// By default, reading stops at the first entry which is truncated, has an
+// invalid time, is for a sector which isn't on the disc, or repeats an
+// earlier entry's sector. Lenient reading instead keeps going, returning
+// those problems as warnings.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use thiserror::Error;
+
+use crate::verify::verify_q_subchannel;
+use crate::{amsf_to_asec, bcd, crc16, lba_to_msf, Disc, CRC16_INITIAL_CRC};
+
+pub const SBI_HEADER: [u8; 4] = *b"SBI\0";
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SidecarError {
+    // Thrown if SBI file exists but doesn't have the correct SBI header
+    #[error("the file doesn't start with an SBI header")]
+    InvalidSbiHeader,
+    #[error("the entry at offset {offset} is truncated: expected {expected} bytes, found {found}")]
+    TruncatedEntry {
+        offset: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[error(
+        "the entry at offset {offset} has an invalid time: {minutes:02X}:{seconds:02X}:{frames:02X}"
+    )]
+    InvalidTime {
+        offset: usize,
+        minutes: u8,
+        seconds: u8,
+        frames: u8,
+    },
+    #[error(
+        "the entry at offset {offset} is for sector {sector}, which isn't on the disc \
+         (sectors {first} to {last})"
+    )]
+    SectorOutOfRange {
+        offset: usize,
+        sector: i64,
+        first: i64,
+        last: i64,
+    },
+    #[error(
+        "the entry at offset {offset} is for sector {sector}, which already has an entry \
+         at offset {first_offset}"
+    )]
+    DuplicateEntry {
+        offset: usize,
+        sector: i64,
+        first_offset: usize,
+    },
+}
+
+/// How to deal with damaged entries in an LSD or SBI file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validation {
+    /// Fail on the first problem
+    Strict,
+    /// Read entries as well as possible, reporting problems as warnings
+    Lenient,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Sidecar {
+    pub q_subchannels: HashMap<i64, Vec<u8>>,
+    /// Problems found while reading in lenient mode
+    pub warnings: Vec<SidecarError>,
+}
+
+fn is_bcd(value: u8) -> bool {
+    value >> 4 <= 9 && value & 0x0F <= 9
+}
+
+fn valid_amsf(m: u8, s: u8, f: u8) -> bool {
+    is_bcd(m) && is_bcd(s) && is_bcd(f) && s < 0x60 && f < 0x75
+}
+
+// Reads fixed-size entries, each starting with a BCD AMSF, from `data`,
+// which starts `base_offset` bytes into the file.
+fn read_entries(
+    data: &[u8],
+    base_offset: usize,
+    entry_size: usize,
+    sector_count: i64,
+    validation: Validation,
+    q_from_entry: impl Fn(&[u8]) -> Vec<u8>,
+) -> Result<Sidecar, SidecarError> {
+    let mut sidecar = Sidecar::default();
+    let mut offsets: HashMap<i64, usize> = HashMap::new();
+    let mut report = |problem: SidecarError| match validation {
+        Validation::Strict => Err(problem),
+        Validation::Lenient => {
+            sidecar.warnings.push(problem);
+            Ok(())
+        }
+    };
+    // The image starts at 00:02:00
+    let first = 150;
+    let last = sector_count + 149;
+
+    let mut entries = vec![];
+    for (i, chunk) in data.chunks(entry_size).enumerate() {
+        let offset = base_offset + i * entry_size;
+        // A truncated entry is treated as though the rest of it were zeroes.
+        let mut entry = chunk.to_vec();
+        if entry.len() < entry_size {
+            report(SidecarError::TruncatedEntry {
+                offset,
+                expected: entry_size,
+                found: entry.len(),
+            })?;
+            entry.resize(entry_size, 0);
+        }
+
+        let (m, s, f) = (entry[0], entry[1], entry[2]);
+        if !valid_amsf(m, s, f) {
+            report(SidecarError::InvalidTime {
+                offset,
+                minutes: m,
+                seconds: s,
+                frames: f,
+            })?;
+        }
+        let sector = amsf_to_asec(m as i64, s as i64, f as i64);
+        if !(first..=last).contains(&sector) {
+            report(SidecarError::SectorOutOfRange {
+                offset,
+                sector,
+                first,
+                last,
+            })?;
+        }
+        // Later entries replace earlier ones
+        if let Some(first_offset) = offsets.insert(sector, offset) {
+            report(SidecarError::DuplicateEntry {
+                offset,
+                sector,
+                first_offset,
+            })?;
+        }
+
+        entries.push((sector, q_from_entry(&entry)));
+    }
+
+    sidecar.q_subchannels = entries.into_iter().collect();
+    Ok(sidecar)
+}
+
+fn bcd_amsf(absolute_sector: i64) -> [u8; 3] {
+    let (m, s, f) = lba_to_msf(absolute_sector);
+    [bcd(m), bcd(s), bcd(f)]
+}
+
+fn sorted(q_subchannels: &HashMap<i64, Vec<u8>>) -> Vec<(&i64, &Vec<u8>)> {
+    let mut entries = q_subchannels.iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(sector, _)| **sector);
+    entries
+}
+
+// LSD File Format:
+// The file consists of subQ data, specifically consisting of the actual AMSF that the current subQ
+// was read from, followed by all 12 bytes of subQ data. LSD is definitively better as a file
+// format for storing subchannel data discrepancies as opposed to SBI, which forces you to
+// generate the CRC16 yourself (something that is a huge problem for SecuROM and LibCrypt if
+// you're aiming for accuracy) and ideally should always be preferred if possible.
+pub fn read_lsd(
+    raw_lsd_data: &[u8],
+    sector_count: i64,
+    validation: Validation,
+) -> Result<Sidecar, SidecarError> {
+    read_entries(raw_lsd_data, 0, 15, sector_count, validation, |entry| {
+        entry[3..15].to_vec()
+    })
+}
+
+pub fn write_lsd<W: Write>(
+    writer: &mut W,
+    q_subchannels: &HashMap<i64, Vec<u8>>,
+) -> io::Result<()> {
+    for (sector, q) in sorted(q_subchannels) {
+        writer.write_all(&bcd_amsf(*sector))?;
+        writer.write_all(&q[0..12])?;
+    }
+
+    Ok(())
+}
+
+// SBI File Format:
+// Starts with header 0x53 0x42 0x49 0x00 ('S' 'B' 'I' '0x00')
+// The entire rest of the file consists of subQ data, specifically consisting of the actual
+// AMSF that the current subQ was read from, followed by a dummy 0x01 byte, followed by the first
+// 10 bytes of that subQ (so, everything but the CRC16). The exclusion of the CRC16 is obviously
+// annoying, *especially* for SecuROM and LibCrypt. LSD is a better file format, but at the
+// moment, redump will only generate LSD files for PS1 discs, and we do not have the power to
+// change the website; so, until a successor website exists, SBI support is necessary. It's
+// also still preferred by a lot of people and emulators for PS1 for some reason, despite
+// being worse than LSD.
+pub fn read_sbi(
+    raw_sbi_data: &[u8],
+    sector_count: i64,
+    validation: Validation,
+) -> Result<Sidecar, SidecarError> {
+    if raw_sbi_data.len() < 4 || raw_sbi_data[0..4] != SBI_HEADER {
+        // Checks for required [S][B][I][0x00] header
+        return Err(SidecarError::InvalidSbiHeader);
+    }
+    read_entries(
+        &raw_sbi_data[4..],
+        4,
+        14,
+        sector_count,
+        validation,
+        |entry| {
+            // Index 3 excluded to ignore dummy 0x01 byte
+            let mut q = entry[4..14].to_vec();
+            // Unlike LSD, SBI is missing the CRC16, so we have to do that
+            // ourselves.
+            let crc = crc16(&q, CRC16_INITIAL_CRC);
+            q.push(((crc >> 8) & 0xFF) as u8);
+            q.push((crc & 0xFF) as u8);
+            q
+        },
+    )
+}
+
+/// Writes an SBI file. The CRC of each entry is discarded, so sectors
+/// with deliberately broken CRCs can't be represented accurately;
+/// prefer LSD wherever possible.
+pub fn write_sbi<W: Write>(
+    writer: &mut W,
+    q_subchannels: &HashMap<i64, Vec<u8>>,
+) -> io::Result<()> {
+    writer.write_all(&SBI_HEADER)?;
+    for (sector, q) in sorted(q_subchannels) {
+        writer.write_all(&bcd_amsf(*sector))?;
+        writer.write_all(&[0x01])?;
+        writer.write_all(&q[0..10])?;
+    }
+
+    Ok(())
+}
+
+/// Combines the entries from an LSD and an SBI for the same disc. Where
+/// both have an entry for a sector, the LSD's is used, since it also has
+/// the original CRC; returns the sectors where the SBI disagrees with it,
+/// sorted.
+pub fn merge(
+    lsd: HashMap<i64, Vec<u8>>,
+    sbi: HashMap<i64, Vec<u8>>,
+) -> (HashMap<i64, Vec<u8>>, Vec<i64>) {
+    let mut conflicts = sbi
+        .iter()
+        // SBI doesn't store the CRC, so only the rest can be compared
+        .filter(|(sector, q)| lsd.get(sector).is_some_and(|l| l[0..10] != q[0..10]))
+        .map(|(sector, _)| *sector)
+        .collect::<Vec<_>>();
+    conflicts.sort();
+
+    let mut merged = sbi;
+    merged.extend(lsd);
+    (merged, conflicts)
+}
+
+/// Finds every sector in a .sub file whose Q subchannel doesn't match the
+/// one generated from the disc's layout, either because its CRC is bad or
+/// because its contents were altered, and returns them keyed by absolute
+/// sector in the form used by LSD and SBI files.
+pub fn extract_q_subchannels<R: Read>(
+    disc: &Disc,
+    sub: &mut R,
+) -> io::Result<HashMap<i64, Vec<u8>>> {
+    let report = verify_q_subchannel(disc, sub)?;
+
+    Ok(report
+        .mismatches
+        .into_iter()
+        // Sector numbers in the report are relative to the start of the
+        // image, but LSD and SBI use absolute times.
+        .map(|mismatch| {
+            (
+                disc.absolute_sector(mismatch.sector),
+                mismatch.actual.to_vec(),
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{merge, read_lsd, read_sbi, write_lsd, write_sbi, SidecarError, Validation};
+
+    const SECTOR_COUNT: i64 = 20000;
+
+    fn q_subchannels() -> HashMap<i64, Vec<u8>> {
+        let mut q_subchannels = HashMap::new();
+        // Altered MSF with a bad CRC, as used by LibCrypt
+        q_subchannels.insert(
+            13955,
+            vec![
+                0x41, 0x01, 0x01, 0x07, 0x06, 0x05, 0x00, 0x23, 0x08, 0x05, 0x38, 0x39,
+            ],
+        );
+        q_subchannels.insert(
+            4650,
+            vec![
+                0x41, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00,
+            ],
+        );
+        q_subchannels
+    }
+
+    #[test]
+    fn test_lsd_round_trip() {
+        let mut lsd = vec![];
+        write_lsd(&mut lsd, &q_subchannels()).unwrap();
+
+        assert_eq!(lsd.len(), 30);
+        // Entries are sorted, with the time in BCD
+        assert_eq!(&lsd[0..3], &[0x01, 0x02, 0x00]);
+        let read = read_lsd(&lsd, SECTOR_COUNT, Validation::Strict).unwrap();
+        assert_eq!(read.q_subchannels, q_subchannels());
+        assert!(read.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_sbi_round_trip() {
+        let mut sbi = vec![];
+        write_sbi(&mut sbi, &q_subchannels()).unwrap();
+
+        assert_eq!(sbi.len(), 4 + 28);
+        assert_eq!(&sbi[0..4], b"SBI\0");
+        assert_eq!(&sbi[4..8], &[0x01, 0x02, 0x00, 0x01]);
+
+        // SBI doesn't store the CRC, so reading it back regenerates a
+        // correct one.
+        let read = read_sbi(&sbi, SECTOR_COUNT, Validation::Strict)
+            .unwrap()
+            .q_subchannels;
+        assert_eq!(read[&13955][0..10], q_subchannels()[&13955][0..10]);
+        assert_ne!(read[&13955][10..12], q_subchannels()[&13955][10..12]);
+    }
+
+    #[test]
+    fn test_strict_errors() {
+        let mut lsd = vec![];
+        write_lsd(&mut lsd, &q_subchannels()).unwrap();
+        let read = |data: &[u8]| read_lsd(data, SECTOR_COUNT, Validation::Strict).err();
+
+        assert_eq!(
+            read(&lsd[..20]),
+            Some(SidecarError::TruncatedEntry {
+                offset: 15,
+                expected: 15,
+                found: 5
+            })
+        );
+
+        let mut bad_bcd = lsd.clone();
+        bad_bcd[16] = 0x0A;
+        assert_eq!(
+            read(&bad_bcd),
+            Some(SidecarError::InvalidTime {
+                offset: 15,
+                minutes: 0x03,
+                seconds: 0x0A,
+                frames: 0x05
+            })
+        );
+
+        // Past the end of the disc
+        let mut out_of_range = lsd.clone();
+        out_of_range[15] = 0x70;
+        assert!(matches!(
+            read(&out_of_range),
+            Some(SidecarError::SectorOutOfRange { offset: 15, .. })
+        ));
+
+        let duplicate = [&lsd[..], &lsd[..15]].concat();
+        assert_eq!(
+            read(&duplicate),
+            Some(SidecarError::DuplicateEntry {
+                offset: 30,
+                sector: 4650,
+                first_offset: 0
+            })
+        );
+
+        // SBI offsets include the header
+        let mut sbi = vec![];
+        write_sbi(&mut sbi, &q_subchannels()).unwrap();
+        assert_eq!(
+            read_sbi(&sbi[..sbi.len() - 1], SECTOR_COUNT, Validation::Strict).err(),
+            Some(SidecarError::TruncatedEntry {
+                offset: 18,
+                expected: 14,
+                found: 13
+            })
+        );
+        assert_eq!(
+            read_sbi(b"SB", SECTOR_COUNT, Validation::Strict).err(),
+            Some(SidecarError::InvalidSbiHeader)
+        );
+    }
+
+    #[test]
+    fn test_lenient_warnings() {
+        let mut lsd = vec![];
+        write_lsd(&mut lsd, &q_subchannels()).unwrap();
+        // A duplicate, then an entry for 00:10:00 with no Q subchannel
+        let damaged = [&lsd[..], &lsd[..15], &[0x00, 0x10, 0x00]].concat();
+
+        let read = read_lsd(&damaged, SECTOR_COUNT, Validation::Lenient).unwrap();
+        assert_eq!(read.warnings.len(), 2);
+        // The truncated entry is zero-filled
+        assert_eq!(read.q_subchannels.len(), 3);
+        assert_eq!(read.q_subchannels[&750], vec![0; 12]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let lsd = q_subchannels();
+        let mut sbi = HashMap::new();
+        // Agrees apart from the CRC
+        let mut q = lsd[&13955].clone();
+        q[10] = 0;
+        sbi.insert(13955, q);
+        // Disagrees
+        sbi.insert(4650, vec![0; 12]);
+        sbi.insert(750, vec![0; 12]);
+
+        let (merged, conflicts) = merge(lsd.clone(), sbi);
+        assert_eq!(conflicts, vec![4650]);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[&4650], lsd[&4650]);
+        assert_eq!(merged[&13955], lsd[&13955]);
+    }
+}
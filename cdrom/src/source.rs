@@ -0,0 +1,439 @@
+// The files a cuesheet references are normally next to it on disk, but disc
+// images are often distributed as ZIP archives. Rather than extracting
+// those first, their members are read straight out of the archive as the
+// image is converted, so even a BIN too large to fit in memory can be used.
+//
+// Members are decompressed as they're read. Stored members can seek
+// anywhere, but seeking backwards within a compressed one means starting
+// over from the beginning of it. The only backwards seeks happen while
+// reading the headers of WAVE and compressed audio files, which are right
+// at the start, so this costs very little.
+//
+// Since we don't go through ZipArchive's own readers, we check each member's
+// CRC-32 ourselves whenever it's read all the way through from the start.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::DeflateDecoder;
+use flate2::Crc;
+use symphonia::core::io::MediaSource;
+use zip::result::ZipResult;
+use zip::{CompressionMethod, ZipArchive};
+
+pub use zip::result::ZipError;
+
+/// Whether `path` looks like a ZIP archive rather than a cuesheet.
+pub fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+fn open_archive(archive: &Path) -> ZipResult<ZipArchive<BufReader<File>>> {
+    ZipArchive::new(BufReader::new(File::open(archive)?))
+}
+
+/// Lists the cuesheets anywhere in a ZIP archive, in order. The resource
+/// forks macOS adds to archives it creates are left out, since they aren't
+/// really cuesheets even though they share their names.
+pub fn cuesheets_in_zip(archive: &Path) -> ZipResult<Vec<String>> {
+    let archive = open_archive(archive)?;
+    let mut cuesheets = archive
+        .file_names()
+        .filter(|name| {
+            let basename = name.rsplit('/').next().unwrap_or(name);
+            !name.starts_with("__MACOSX/")
+                && !basename.starts_with("._")
+                && Path::new(basename)
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("cue"))
+        })
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    cuesheets.sort();
+    Ok(cuesheets)
+}
+
+/// Where the files referenced by a cuesheet are read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FileSource {
+    /// A directory on disk
+    Directory(PathBuf),
+    /// A directory inside a ZIP archive; `dir` is empty for the top of the
+    /// archive, and otherwise ends with a slash
+    Zip { archive: PathBuf, dir: String },
+}
+
+impl FileSource {
+    /// The directory containing `member`, a file within `archive`.
+    pub fn zip_containing(archive: &Path, member: &str) -> FileSource {
+        let dir = match member.rfind('/') {
+            Some(end) => &member[..=end],
+            None => "",
+        };
+        FileSource::Zip {
+            archive: archive.to_path_buf(),
+            dir: dir.to_string(),
+        }
+    }
+
+    // The name of a file within the archive. ZIP archives always use
+    // forward slashes, but cuesheets written on Windows might not.
+    fn member_name(dir: &str, name: &Path) -> String {
+        format!("{}{}", dir, name.to_string_lossy().replace('\\', "/"))
+    }
+
+    /// Where `name` is, for showing to the user. Files inside an archive
+    /// are shown as if the archive were a directory.
+    pub fn path(&self, name: &Path) -> PathBuf {
+        match self {
+            FileSource::Directory(root) => root.join(name),
+            FileSource::Zip { archive, dir } => archive.join(FileSource::member_name(dir, name)),
+        }
+    }
+
+    pub fn is_file(&self, name: &Path) -> bool {
+        match self {
+            FileSource::Directory(root) => root.join(name).is_file(),
+            FileSource::Zip { archive, dir } => open_archive(archive).is_ok_and(|mut archive| {
+                archive
+                    .by_name(&FileSource::member_name(dir, name))
+                    .is_ok_and(|member| member.is_file())
+            }),
+        }
+    }
+
+    /// The names of the files in the directory, not counting any in its
+    /// subdirectories.
+    pub fn file_names(&self) -> io::Result<Vec<PathBuf>> {
+        match self {
+            FileSource::Directory(root) => {
+                // A bare filename has an empty parent
+                let root = if root.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    root
+                };
+                let mut names = vec![];
+                for entry in std::fs::read_dir(root)? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_file() {
+                        names.push(PathBuf::from(entry.file_name()));
+                    }
+                }
+                Ok(names)
+            }
+            FileSource::Zip { archive, dir } => Ok(open_archive(archive)?
+                .file_names()
+                .filter_map(|name| name.strip_prefix(dir.as_str()))
+                .filter(|name| !name.is_empty() && !name.contains('/'))
+                .map(PathBuf::from)
+                .collect()),
+        }
+    }
+
+    /// The length of a file in bytes, which for a member of an archive is
+    /// its length once it's been decompressed.
+    pub fn length(&self, name: &Path) -> io::Result<u64> {
+        match self {
+            FileSource::Directory(root) => Ok(root.join(name).metadata()?.len()),
+            FileSource::Zip { archive, dir } => Ok(open_archive(archive)?
+                .by_name(&FileSource::member_name(dir, name))?
+                .size()),
+        }
+    }
+
+    pub fn open(&self, name: &Path) -> io::Result<SourceFile> {
+        match self {
+            FileSource::Directory(root) => Ok(SourceFile::File(File::open(root.join(name))?)),
+            FileSource::Zip { archive, dir } => Ok(SourceFile::Zip(ZipMember::open(
+                archive,
+                &FileSource::member_name(dir, name),
+            )?)),
+        }
+    }
+
+    /// Reads the whole of a file into memory.
+    pub fn read(&self, name: &Path) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        self.open(name)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// A file opened from a FileSource.
+pub enum SourceFile {
+    File(File),
+    Zip(ZipMember),
+}
+
+impl Read for SourceFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SourceFile::File(file) => file.read(buf),
+            SourceFile::Zip(member) => member.read(buf),
+        }
+    }
+}
+
+impl Seek for SourceFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            SourceFile::File(file) => file.seek(pos),
+            SourceFile::Zip(member) => member.seek(pos),
+        }
+    }
+}
+
+// Lets compressed audio be decoded straight out of an archive
+impl MediaSource for SourceFile {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        match self {
+            SourceFile::File(file) => file.metadata().ok().map(|metadata| metadata.len()),
+            SourceFile::Zip(member) => Some(member.size),
+        }
+    }
+}
+
+/// A reader for a single member of a ZIP archive.
+///
+/// ZipArchive only lends out readers for its members, so this instead
+/// keeps its own handle on the archive, positioned at the member's data.
+pub struct ZipMember {
+    archive: PathBuf,
+    name: String,
+    // Where the member's data starts within the archive
+    data_start: u64,
+    compressed_size: u64,
+    compression: CompressionMethod,
+    // Decompressed length
+    size: u64,
+    // The CRC-32 the archive records for the decompressed data, and the
+    // CRC-32 of what's been read so far, if the reader started from the
+    // beginning of the member
+    crc32: u32,
+    crc: Option<Crc>,
+    reader: Box<dyn Read + Send + Sync>,
+    // Where the reader is in the decompressed data, which can be behind
+    // `position` if we've been asked to seek somewhere we haven't read
+    // up to yet
+    reader_position: u64,
+    position: u64,
+}
+
+impl ZipMember {
+    fn open(archive: &Path, name: &str) -> io::Result<ZipMember> {
+        let mut zip = open_archive(archive)?;
+        let member = zip.by_name(name)?;
+        if !matches!(
+            member.compression(),
+            CompressionMethod::Stored | CompressionMethod::Deflated
+        ) {
+            return Err(ZipError::UnsupportedArchive(
+                "Only stored and deflated members can be read",
+            )
+            .into());
+        }
+
+        let mut member = ZipMember {
+            archive: archive.to_path_buf(),
+            name: name.to_string(),
+            data_start: member.data_start(),
+            compressed_size: member.compressed_size(),
+            compression: member.compression(),
+            size: member.size(),
+            crc32: member.crc32(),
+            crc: None,
+            reader: Box::new(io::empty()),
+            reader_position: 0,
+            position: 0,
+        };
+        member.reopen(0)?;
+        Ok(member)
+    }
+
+    // Starts reading over again, as close as possible to `position`.
+    // Stored members can be started from anywhere, but compressed ones
+    // have to be decompressed from the beginning.
+    fn reopen(&mut self, position: u64) -> io::Result<()> {
+        let mut file = BufReader::new(File::open(&self.archive)?);
+        if self.compression == CompressionMethod::Stored {
+            let position = position.min(self.size);
+            file.seek(SeekFrom::Start(self.data_start + position))?;
+            self.reader = Box::new(file.take(self.size - position));
+            self.reader_position = position;
+            self.crc = (position == 0).then(Crc::new);
+        } else {
+            file.seek(SeekFrom::Start(self.data_start))?;
+            self.reader = Box::new(DeflateDecoder::new(file.take(self.compressed_size)));
+            self.reader_position = 0;
+            self.crc = Some(Crc::new());
+        }
+        Ok(())
+    }
+
+    // Reads from wherever the reader is, checking the CRC-32 once the
+    // end of the member is reached.
+    fn read_next(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.reader_position += read as u64;
+        if let Some(crc) = &mut self.crc {
+            crc.update(&buf[..read]);
+            if self.reader_position == self.size && crc.sum() != self.crc32 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is damaged: its CRC-32 doesn't match", self.name),
+                ));
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Read for ZipMember {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+        if self.position < self.reader_position {
+            self.reopen(self.position)?;
+        }
+        // Skipped data still goes into the CRC-32
+        let mut skip_buf = [0; 8192];
+        while self.position > self.reader_position {
+            let skip = (self.position - self.reader_position).min(skip_buf.len() as u64);
+            if self.read_next(&mut skip_buf[..skip as usize])? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+
+        let read = self.read_next(buf)?;
+        self.position = self.reader_position;
+        Ok(read)
+    }
+}
+
+impl Seek for ZipMember {
+    // Seeking only moves where the next read will start from; nothing is
+    // read until then, so that seeking to the end to find the length
+    // doesn't decompress the whole thing.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        };
+        self.position = position;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    use super::FileSource;
+
+    #[test]
+    fn test_zip_members() {
+        let archive = std::env::temp_dir().join(format!("cdrom-source-{}.zip", std::process::id()));
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        for (name, compression) in [
+            ("dir/stored.bin", CompressionMethod::Stored),
+            ("dir/deflated.bin", CompressionMethod::Deflated),
+        ] {
+            let options = FileOptions::default().compression_method(compression);
+            zip.start_file(name, options).unwrap();
+            zip.write_all(&data).unwrap();
+        }
+        zip.add_directory("dir/subdir", FileOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+
+        let files = FileSource::zip_containing(&archive, "dir/stored.bin");
+        let mut names = files.file_names().unwrap();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![Path::new("deflated.bin"), Path::new("stored.bin")]
+        );
+        assert!(!files.is_file(Path::new("missing.bin")));
+        assert_eq!(
+            files.path(Path::new("stored.bin")),
+            archive.join("dir/stored.bin")
+        );
+
+        for name in ["stored.bin", "deflated.bin"] {
+            let name = Path::new(name);
+            assert!(files.is_file(name));
+            assert_eq!(files.length(name).unwrap(), data.len() as u64);
+            assert_eq!(files.read(name).unwrap(), data);
+
+            // Forwards, to the end and back again
+            let mut file = files.open(name).unwrap();
+            let mut buf = [0; 4];
+            file.seek(SeekFrom::Start(50_000)).unwrap();
+            file.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[50_000..50_004]);
+            assert_eq!(file.seek(SeekFrom::End(0)).unwrap(), data.len() as u64);
+            assert_eq!(file.read(&mut buf).unwrap(), 0);
+            file.seek(SeekFrom::Start(10)).unwrap();
+            file.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[10..14]);
+            file.seek(SeekFrom::Current(-4)).unwrap();
+            file.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[10..14]);
+        }
+
+        std::fs::remove_file(&archive).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_zip_member() {
+        let archive =
+            std::env::temp_dir().join(format!("cdrom-damaged-{}.zip", std::process::id()));
+        let data = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("disc.bin", options).unwrap();
+        zip.write_all(&data).unwrap();
+        zip.finish().unwrap();
+
+        // Flip a byte in the middle of the stored data
+        let mut bytes = std::fs::read(&archive).unwrap();
+        let data_start = bytes.windows(data.len()).position(|w| w == data).unwrap();
+        bytes[data_start + 5_000] ^= 0xFF;
+        std::fs::write(&archive, bytes).unwrap();
+
+        let files = FileSource::zip_containing(&archive, "disc.bin");
+        let name = Path::new("disc.bin");
+        let error = files.read(name).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        // Reading only part of it doesn't check the CRC-32
+        let mut file = files.open(name).unwrap();
+        let mut buf = [0; 4];
+        file.seek(SeekFrom::Start(10)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[10..14]);
+
+        std::fs::remove_file(&archive).unwrap();
+    }
+}
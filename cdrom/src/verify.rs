@@ -0,0 +1,205 @@
+// Subchannel data read from real discs isn't always trustworthy; flaky
+// drives can return corrupted Q subchannel data without reporting an error.
+// The Q subchannel of every sector is protected by a CRC16, and its contents
+// are almost entirely predictable from the disc's layout, so we can check
+// a .sub file by comparing each sector against what we'd generate ourselves.
+//
+// Not every difference is an error: copy protection schemes such as
+// LibCrypt deliberately write sectors with broken Q data, which is what
+// LSD and SBI files exist to preserve.
+
+use std::io::{self, Read};
+
+use crate::{crc16, Disc, CRC16_INITIAL_CRC};
+
+/// Length of the subchannel data for a single sector in a .sub file.
+pub const SUBCHANNEL_SIZE: usize = 96;
+
+/// Whether a Q subchannel frame's CRC matches its contents.
+pub fn q_crc_is_valid(q: &[u8]) -> bool {
+    let crc = crc16(&q[0..10], CRC16_INITIAL_CRC);
+    q[10] == (crc >> 8) as u8 && q[11] == (crc & 0xFF) as u8
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// A time in minutes, seconds and frames, as stored in the Q subchannel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Msf {
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+}
+
+impl std::fmt::Display for Msf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
+/// The fields of a mode 1 (position) Q subchannel frame, decoded from BCD.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QPosition {
+    pub control: u8,
+    pub adr: u8,
+    pub track: u8,
+    pub index: u8,
+    pub relative: Msf,
+    pub absolute: Msf,
+}
+
+impl QPosition {
+    pub fn decode(q: &[u8]) -> QPosition {
+        let msf = |at: usize| Msf {
+            minutes: from_bcd(q[at]),
+            seconds: from_bcd(q[at + 1]),
+            frames: from_bcd(q[at + 2]),
+        };
+
+        QPosition {
+            control: q[0] >> 4,
+            adr: q[0] & 0x0F,
+            track: from_bcd(q[1]),
+            index: from_bcd(q[2]),
+            relative: msf(3),
+            absolute: msf(7),
+        }
+    }
+}
+
+/// A sector whose Q subchannel doesn't match the one we'd generate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QMismatch {
+    /// Sector number, relative to the start of the image
+    pub sector: i64,
+    pub crc_valid: bool,
+    pub expected: [u8; 12],
+    pub actual: [u8; 12],
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of sectors whose subchannel data was checked
+    pub sectors_checked: i64,
+    /// Number of sectors on the disc missing from the end of the .sub
+    pub sectors_missing: i64,
+    pub mismatches: Vec<QMismatch>,
+}
+
+/// Compares the Q subchannel data in a .sub file against the data
+/// generated from the disc's layout.
+///
+/// Frames with a valid CRC that contain a media catalog number or ISRC
+/// rather than position data aren't reported, since they're legitimate
+/// and we don't generate them.
+pub fn verify_q_subchannel<R: Read>(disc: &Disc, sub: &mut R) -> io::Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut buf = [0; SUBCHANNEL_SIZE];
+
+    for sector in disc.sectors() {
+        match sub.read_exact(&mut buf) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                report.sectors_missing = disc.sector_count - sector.start;
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+        report.sectors_checked += 1;
+
+        let actual: [u8; 12] = buf[12..24].try_into().unwrap();
+        let expected = sector.generate_q_subchannel_from_layout(&None);
+        if actual == expected {
+            continue;
+        }
+
+        let crc_valid = q_crc_is_valid(&actual);
+        if crc_valid && matches!(actual[0] & 0x0F, 2 | 3) {
+            continue;
+        }
+
+        report.mismatches.push(QMismatch {
+            sector: sector.start,
+            crc_valid,
+            expected,
+            actual,
+        });
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{verify_q_subchannel, QPosition, SUBCHANNEL_SIZE};
+    use crate::{Disc, TrackMode};
+
+    #[test]
+    fn test_verify_reports_bad_sectors() {
+        let disc = Disc::test_single_track(TrackMode::Mode1Raw, 300);
+        let mut sub = vec![];
+        for sector in disc.sectors() {
+            sub.extend(sector.generate_subchannel(&None, &HashMap::new()));
+        }
+        // A corrupted frame, and a frame with a valid CRC but the wrong time
+        sub[100 * SUBCHANNEL_SIZE + 15] ^= 0x01;
+        let mut altered = disc
+            .sectors()
+            .nth(200)
+            .unwrap()
+            .generate_q_subchannel_from_layout(&None);
+        altered[9] = 0x74;
+        let crc = cdrom_crc::crc16(&altered[0..10], cdrom_crc::CRC16_INITIAL_CRC);
+        altered[10] = (crc >> 8) as u8;
+        altered[11] = (crc & 0xFF) as u8;
+        sub[200 * SUBCHANNEL_SIZE + 12..200 * SUBCHANNEL_SIZE + 24].copy_from_slice(&altered);
+        sub.truncate(290 * SUBCHANNEL_SIZE);
+
+        let report = verify_q_subchannel(&disc, &mut &sub[..]).unwrap();
+
+        assert_eq!(report.sectors_checked, 290);
+        assert_eq!(report.sectors_missing, 10);
+        assert_eq!(report.mismatches.len(), 2);
+        assert_eq!(report.mismatches[0].sector, 100);
+        assert!(!report.mismatches[0].crc_valid);
+        assert_eq!(report.mismatches[1].sector, 200);
+        assert!(report.mismatches[1].crc_valid);
+
+        let position = QPosition::decode(&report.mismatches[1].actual);
+        assert_eq!(position.track, 1);
+        assert_eq!(position.relative.to_string(), "00:02:50");
+        assert_eq!(position.absolute.to_string(), "00:04:74");
+    }
+
+    #[test]
+    fn test_verify_skips_q_mode_frames() {
+        let mut disc = Disc::test_single_track(TrackMode::Mode1Raw, 300);
+        disc.catalog = Some("0724384260927".parse().unwrap());
+        disc.tracks[0].mode = TrackMode::Audio;
+        disc.tracks[0].isrc = Some("USABC1234567".parse().unwrap());
+        let mut sub = vec![];
+        for sector in disc.sectors() {
+            sub.extend(sector.generate_subchannel(&None, &HashMap::new()));
+        }
+        let frames = |mode| {
+            sub.chunks(SUBCHANNEL_SIZE)
+                .filter(|sector| sector[12] & 0x0F == mode)
+                .count()
+        };
+        // Absolute sectors 200, 300 and 400
+        assert_eq!(frames(2), 3);
+        // Absolute sectors 250 and 350; 150 is the first frame of the track
+        assert_eq!(frames(3), 2);
+
+        let report = verify_q_subchannel(&disc, &mut &sub[..]).unwrap();
+        assert!(report.mismatches.is_empty());
+    }
+}
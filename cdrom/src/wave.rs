@@ -0,0 +1,198 @@
+// Cuesheets can reference WAVE files instead of raw BIN files for audio
+// tracks. As long as the audio is already in CD-DA format (44.1kHz 16-bit
+// stereo PCM), the sample data in a WAVE file is byte-for-byte identical to
+// a raw audio track; we just need to find where it is within the file.
+//
+// WAVE files are RIFF files, which consist of a header followed by a series
+// of chunks. The only two we care about are the "fmt " chunk, which
+// describes the audio format, and the "data" chunk, which contains the
+// samples themselves. Any other chunks, such as metadata, are skipped.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use thiserror::Error;
+
+const FORMAT_PCM: u16 = 1;
+// Used by some tools even for plain PCM; the real format is stored in
+// the first two bytes of the subformat GUID at the end of the chunk.
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Error, Debug)]
+pub enum WaveError {
+    #[error("not a RIFF WAVE file")]
+    NotWave,
+
+    #[error("audio must be PCM, but the file uses format {0:#06x}")]
+    NotPcm(u16),
+
+    #[error("audio must be 44.1kHz 16-bit stereo, but the file is {sample_rate}Hz {bits_per_sample}-bit with {channels} channel(s)")]
+    NotCdda {
+        sample_rate: u32,
+        bits_per_sample: u16,
+        channels: u16,
+    },
+
+    #[error("the file doesn't contain a {0} chunk")]
+    MissingChunk(&'static str),
+
+    #[error(transparent)]
+    IO(#[from] io::Error),
+}
+
+/// The location of the audio samples within a WAVE file.
+#[derive(Clone, Copy, Debug)]
+pub struct WaveData {
+    /// Offset in bytes from the start of the file
+    pub offset: u64,
+    /// Length of the sample data in bytes
+    pub length: u64,
+}
+
+impl WaveData {
+    pub fn open(path: &Path) -> Result<WaveData, WaveError> {
+        WaveData::read(&mut File::open(path)?)
+    }
+
+    /// Reads the headers of a WAVE file, checking that it contains
+    /// CD-DA audio, and returns the location of its samples.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<WaveData, WaveError> {
+        let mut header = [0; 12];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| WaveError::NotWave)?;
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            return Err(WaveError::NotWave);
+        }
+
+        let mut found_format = false;
+        loop {
+            let mut chunk_header = [0; 8];
+            if reader.read_exact(&mut chunk_header).is_err() {
+                break;
+            }
+            let id = &chunk_header[0..4];
+            let size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap()) as u64;
+
+            match id {
+                b"fmt " => {
+                    let mut format = vec![0; size as usize];
+                    reader.read_exact(&mut format)?;
+                    validate_format(&format)?;
+                    found_format = true;
+                }
+                b"data" => {
+                    if !found_format {
+                        return Err(WaveError::MissingChunk("fmt"));
+                    }
+                    let offset = reader.stream_position()?;
+                    // Some tools write a placeholder size when streaming,
+                    // so don't trust a size running past the end of the file.
+                    let file_size = reader.seek(SeekFrom::End(0))?;
+                    return Ok(WaveData {
+                        offset,
+                        length: size.min(file_size - offset),
+                    });
+                }
+                _ => {
+                    // Chunks are padded to an even number of bytes.
+                    reader.seek(SeekFrom::Current((size + (size & 1)) as i64))?;
+                }
+            }
+        }
+
+        Err(WaveError::MissingChunk("data"))
+    }
+}
+
+fn validate_format(format: &[u8]) -> Result<(), WaveError> {
+    if format.len() < 16 {
+        return Err(WaveError::NotWave);
+    }
+    let read_u16 = |at: usize| u16::from_le_bytes([format[at], format[at + 1]]);
+
+    let mut format_tag = read_u16(0);
+    if format_tag == FORMAT_EXTENSIBLE && format.len() >= 26 {
+        format_tag = read_u16(24);
+    }
+    if format_tag != FORMAT_PCM {
+        return Err(WaveError::NotPcm(format_tag));
+    }
+
+    let channels = read_u16(2);
+    let sample_rate = u32::from_le_bytes(format[4..8].try_into().unwrap());
+    let bits_per_sample = read_u16(14);
+    if channels != 2 || sample_rate != 44100 || bits_per_sample != 16 {
+        return Err(WaveError::NotCdda {
+            sample_rate,
+            bits_per_sample,
+            channels,
+        });
+    }
+
+    Ok(())
+}
+
+/// Whether a file referenced by a cuesheet is a WAVE file.
+/// The file type in the FILE command is frequently wrong, so we go by
+/// the extension instead.
+pub fn is_wave(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{WaveData, WaveError};
+
+    fn wave_header(channels: u16, sample_rate: u32, data_length: u32) -> Vec<u8> {
+        let mut header = vec![];
+        header.extend(b"RIFF");
+        header.extend((36 + 12 + data_length).to_le_bytes());
+        header.extend(b"WAVE");
+        header.extend(b"fmt ");
+        header.extend(16u32.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(channels.to_le_bytes());
+        header.extend(sample_rate.to_le_bytes());
+        header.extend((sample_rate * channels as u32 * 2).to_le_bytes());
+        header.extend((channels * 2).to_le_bytes());
+        header.extend(16u16.to_le_bytes());
+        // An unrelated chunk with an odd size, which should be skipped
+        header.extend(b"LIST");
+        header.extend(3u32.to_le_bytes());
+        header.extend([0; 4]);
+        header.extend(b"data");
+        header.extend(data_length.to_le_bytes());
+        header.extend(vec![0; data_length as usize]);
+
+        header
+    }
+
+    #[test]
+    fn test_wave_data_location() {
+        let wave = wave_header(2, 44100, 2352);
+        let data = WaveData::read(&mut Cursor::new(wave)).unwrap();
+
+        assert_eq!(data.offset, 56);
+        assert_eq!(data.length, 2352);
+    }
+
+    #[test]
+    fn test_wave_rejects_non_cdda() {
+        let wave = wave_header(1, 48000, 2352);
+        let error = WaveData::read(&mut Cursor::new(wave)).unwrap_err();
+
+        assert!(matches!(
+            error,
+            WaveError::NotCdda {
+                sample_rate: 48000,
+                channels: 1,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,348 @@
+// Converts every cuesheet in a directory tree, for archives with far too
+// many discs to convert one at a time. A cuesheet which can't be converted
+// doesn't stop the rest; everything that went wrong is reported at the end.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use cdrom::convert::{ConversionReport, Converter};
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::{conversion_options, print_report, Args, Cue2CCDError};
+
+/// Something which went wrong partway through a batch, without stopping it.
+#[derive(Error, Debug, Diagnostic)]
+pub enum BatchFailure {
+    #[error("Couldn't convert {filename}")]
+    Conversion {
+        filename: String,
+        #[diagnostic_source]
+        source: Cue2CCDError,
+    },
+
+    #[error("Couldn't look for cuesheets in {dirname}")]
+    UnreadableDirectory { dirname: String, source: io::Error },
+}
+
+// Sums up the failures for BatchError.
+pub(crate) fn describe_failures(total: usize, failures: &[BatchFailure]) -> String {
+    let unconverted = failures
+        .iter()
+        .filter(|failure| matches!(failure, BatchFailure::Conversion { .. }))
+        .count();
+    let unreadable = failures.len() - unconverted;
+    let cuesheets = format!(
+        "{} of {} cuesheets couldn't be converted",
+        unconverted, total
+    );
+    let directories = format!("{} directories couldn't be read", unreadable);
+    match (unconverted, unreadable) {
+        (_, 0) => cuesheets,
+        (0, _) => directories,
+        _ => format!("{}, and {}", cuesheets, directories),
+    }
+}
+
+enum Outcome {
+    Converted(ConversionReport),
+    // The cuesheet already has a .ccd
+    Skipped,
+    Failed(Cue2CCDError),
+}
+
+// Finds every .cue under `dir`, in a consistent order. Symlinks to
+// directories aren't followed, in case they loop back on themselves.
+// Directories which can't be read are skipped over and recorded in
+// `failures`, rather than losing everything else.
+fn find_cuesheets(dir: &Path, found: &mut Vec<PathBuf>, failures: &mut Vec<BatchFailure>) {
+    let entries = std::fs::read_dir(dir).and_then(|entries| entries.collect::<Result<Vec<_>, _>>());
+    let mut entries = match entries {
+        Ok(entries) => entries,
+        Err(source) => {
+            failures.push(BatchFailure::UnreadableDirectory {
+                dirname: dir.display().to_string(),
+                source,
+            });
+            return;
+        }
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => find_cuesheets(&path, found, failures),
+            Ok(_) => {
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("cue"))
+                {
+                    found.push(path);
+                }
+            }
+            Err(source) => failures.push(BatchFailure::UnreadableDirectory {
+                dirname: path.display().to_string(),
+                source,
+            }),
+        }
+    }
+}
+
+fn convert(args: &Args, root: &Path, cue_path: &Path) -> Outcome {
+    // The output goes in the same place relative to --output-path as the
+    // cuesheet is relative to the directory being converted.
+    let output_dir = args.output_path.as_ref().map(|output_path| {
+        let relative = cue_path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(root).ok())
+            .unwrap_or(Path::new(""));
+        Path::new(output_path).join(relative)
+    });
+
+    let ccd_path = cue_path.with_extension("ccd");
+    let ccd_path = match (&output_dir, ccd_path.file_name()) {
+        (Some(output_dir), Some(ccd_name)) => output_dir.join(ccd_name),
+        _ => ccd_path,
+    };
+    if ccd_path.exists() {
+        return Outcome::Skipped;
+    }
+
+    if let Some(output_dir) = &output_dir {
+        if let Err(error) = std::fs::create_dir_all(output_dir) {
+            return Outcome::Failed(error.into());
+        }
+    }
+    let options = conversion_options(args, cue_path, output_dir);
+    match Converter::new(options).convert() {
+        Ok(report) => Outcome::Converted(report),
+        Err(error) => Outcome::Failed(error.into()),
+    }
+}
+
+fn print_outcome(cue_path: &Path, outcome: &Outcome) {
+    match outcome {
+        Outcome::Converted(report) => {
+            eprintln!("Converted {}", cue_path.display());
+            print_report(report);
+        }
+        Outcome::Skipped => eprintln!("Skipped {}, which already has a .ccd", cue_path.display()),
+        Outcome::Failed(error) => eprintln!("Couldn't convert {}: {}", cue_path.display(), error),
+    }
+}
+
+/// Converts every cuesheet in `root` and its subdirectories, running up to
+/// --jobs conversions at once.
+pub fn convert_tree(args: &Args, root: &Path) -> Result<(), Cue2CCDError> {
+    if !root.is_dir() {
+        return Err(Cue2CCDError::NotADirectoryError {
+            filename: root.display().to_string(),
+        });
+    }
+
+    let mut cuesheets = vec![];
+    let mut failures = vec![];
+    find_cuesheets(root, &mut cuesheets, &mut failures);
+    for failure in &failures {
+        if let BatchFailure::UnreadableDirectory { dirname, source } = failure {
+            eprintln!("Couldn't read {}: {}", dirname, source);
+        }
+    }
+    if cuesheets.is_empty() && failures.is_empty() {
+        eprintln!("No cuesheets found in {}", root.display());
+        return Ok(());
+    }
+    eprintln!("Found {} cuesheets in {}", cuesheets.len(), root.display());
+
+    // Each thread takes the next cuesheet nobody has started on until
+    // they've all been converted.
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new(vec![]);
+    let jobs = args.jobs.get().min(cuesheets.len());
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                while let Some(cue_path) = cuesheets.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let outcome = convert(args, root, cue_path);
+                    // Printed while holding the lock, so that the messages
+                    // about each cuesheet stay together
+                    let mut outcomes = outcomes.lock().unwrap();
+                    print_outcome(cue_path, &outcome);
+                    outcomes.push((cue_path, outcome));
+                }
+            });
+        }
+    });
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(cue_path, _)| *cue_path);
+    let mut converted = 0;
+    let mut skipped = 0;
+    let mut failed = 0;
+    for (cue_path, outcome) in outcomes {
+        match outcome {
+            Outcome::Converted(_) => converted += 1,
+            Outcome::Skipped => skipped += 1,
+            Outcome::Failed(source) => {
+                failed += 1;
+                failures.push(BatchFailure::Conversion {
+                    filename: cue_path.display().to_string(),
+                    source,
+                })
+            }
+        }
+    }
+
+    eprintln!(
+        "Converted {}, skipped {} which already had a .ccd, failed {}",
+        converted, skipped, failed
+    );
+    if !failures.is_empty() {
+        return Err(Cue2CCDError::BatchError {
+            total: cuesheets.len(),
+            failures,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::{convert, find_cuesheets, BatchFailure, Outcome};
+    use crate::{temp_dir, Args};
+
+    #[test]
+    fn test_failed_conversion_is_retried() {
+        let root = temp_dir("retry");
+        let cue_path = root.join("disc.cue");
+        std::fs::write(
+            &cue_path,
+            "FILE \"disc.bin\" BINARY\n  TRACK 01 MODE1/2048\n    INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\n    INDEX 01 00:01:00\n",
+        )
+        .unwrap();
+        // The data track should be 75 cooked sectors long, but the file
+        // stops partway through it.
+        std::fs::write(root.join("disc.bin"), vec![0; 10 * 2048]).unwrap();
+        let args = Args::parse_from(["cue2ccd", "--recursive", root.to_str().unwrap()]);
+
+        assert!(matches!(
+            convert(&args, &root, &cue_path),
+            Outcome::Failed(_)
+        ));
+        assert!(!root.join("disc.ccd").exists());
+        assert!(!root.join("disc.img").exists());
+        assert!(!root.join("disc.img.partial").exists());
+
+        std::fs::write(root.join("disc.bin"), vec![0; 75 * 2048 + 75 * 2352]).unwrap();
+        assert!(matches!(
+            convert(&args, &root, &cue_path),
+            Outcome::Converted(_)
+        ));
+        assert!(root.join("disc.ccd").exists());
+        assert_eq!(
+            std::fs::metadata(root.join("disc.img")).unwrap().len(),
+            150 * 2352
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_directory() {
+        let root = temp_dir("unreadable");
+        for dir in ["a", "b", "c"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("disc.cue"), "").unwrap();
+        }
+        std::fs::write(root.join("b").join("other.CUE"), "").unwrap();
+        std::fs::write(root.join("b").join("disc.bin"), "").unwrap();
+
+        let mut found = vec![];
+        let mut failures = vec![];
+        find_cuesheets(&root, &mut found, &mut failures);
+        assert_eq!(
+            found,
+            vec![
+                root.join("a").join("disc.cue"),
+                root.join("b").join("disc.cue"),
+                root.join("b").join("other.CUE"),
+                root.join("c").join("disc.cue"),
+            ]
+        );
+        assert!(failures.is_empty());
+
+        // A directory that can't be read is recorded rather than returned.
+        // Permissions don't stop root, so a directory that's gone missing
+        // stands in for one.
+        let mut found = vec![];
+        find_cuesheets(&root.join("missing"), &mut found, &mut failures);
+        assert!(found.is_empty());
+        assert!(matches!(
+            &failures[..],
+            [BatchFailure::UnreadableDirectory { dirname, .. }]
+                if *dirname == root.join("missing").display().to_string()
+        ));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_output_path_and_skipping() {
+        let root = temp_dir("batch");
+        let cue_path = root.join("Some Game").join("Disc 1").join("disc.cue");
+        std::fs::create_dir_all(cue_path.parent().unwrap()).unwrap();
+        let bincue = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("dataplusaudio")
+            .join("bincue");
+        for name in ["disc.cue", "disc.bin"] {
+            std::fs::copy(bincue.join(name), cue_path.with_file_name(name)).unwrap();
+        }
+
+        // The output mirrors where the cuesheet is under the root
+        let output = root.join("output");
+        let args = Args::parse_from([
+            "cue2ccd",
+            "--recursive",
+            "--skip-img-copy",
+            "--output-path",
+            output.to_str().unwrap(),
+            root.to_str().unwrap(),
+        ]);
+        match convert(&args, &root, &cue_path) {
+            Outcome::Converted(report) => assert_eq!(
+                report.ccd,
+                output.join("Some Game").join("Disc 1").join("disc.ccd")
+            ),
+            _ => panic!("expected {} to be converted", cue_path.display()),
+        }
+        assert!(!cue_path.with_extension("ccd").exists());
+        // The .ccd in the output is what marks it as done
+        assert!(matches!(convert(&args, &root, &cue_path), Outcome::Skipped));
+
+        let args = Args::parse_from([
+            "cue2ccd",
+            "--recursive",
+            "--skip-img-copy",
+            root.to_str().unwrap(),
+        ]);
+        assert!(matches!(
+            convert(&args, &root, &cue_path),
+            Outcome::Converted(_)
+        ));
+        assert!(cue_path.with_extension("ccd").is_file());
+        assert!(matches!(convert(&args, &root, &cue_path), Outcome::Skipped));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
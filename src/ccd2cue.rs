@@ -0,0 +1,158 @@
+// Converts CloneCD images back into BIN/CUE, for emulators and tools which
+// only understand cuesheets.
+//
+// The layout of the disc comes from the CCD control file. The .img is always
+// raw, so it can be referenced by the cuesheet as-is or split into one BIN
+// per track.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use cdrom::raw::RAW_SECTOR_SIZE;
+use cdrom::{Disc, Track, TrackMode};
+use clap::Args;
+
+use crate::{extract, read_ccd, Cue2CCDError};
+
+#[derive(Args, Debug)]
+pub struct Ccd2CueArgs {
+    filename: String,
+    #[arg(long)]
+    output_path: Option<String>,
+    /// Write one BIN file per track instead of referencing the .img
+    #[arg(long, default_value_t = false)]
+    split: bool,
+    /// Write an LSD file with the Q subchannel data that differs from
+    /// what would be generated for the disc
+    #[arg(long, default_value_t = false)]
+    lsd: bool,
+    /// Write an SBI file with the Q subchannel data that differs from
+    /// what would be generated for the disc
+    #[arg(long, default_value_t = false)]
+    sbi: bool,
+}
+
+fn disc_from_ccd(ccd_path: &Path, img: &mut File) -> Result<Disc, Cue2CCDError> {
+    let (ccd, mut disc) = read_ccd(ccd_path)?;
+
+    // Without [TRACK n] sections, the CCD can only tell us whether a track
+    // is audio or data; the real data mode is in the header of the track's
+    // first sector.
+    if ccd.tracks.is_empty() {
+        for track in disc.tracks.iter_mut() {
+            if let TrackMode::Audio = track.mode {
+                continue;
+            }
+            let mut header = [0; 16];
+            img.seek(SeekFrom::Start(track.start as u64 * RAW_SECTOR_SIZE as u64))?;
+            img.read_exact(&mut header)?;
+            track.mode = if header[15] == 2 {
+                TrackMode::Mode2Raw
+            } else {
+                TrackMode::Mode1Raw
+            };
+        }
+    }
+
+    Ok(disc)
+}
+
+// Names split tracks the same way redump does.
+fn split_filename(stem: &str, track: &Track, track_count: usize) -> String {
+    if track_count == 1 {
+        format!("{}.bin", stem)
+    } else if track_count >= 10 {
+        format!("{} (Track {:02}).bin", stem, track.number)
+    } else {
+        format!("{} (Track {}).bin", stem, track.number)
+    }
+}
+
+pub fn convert(args: Ccd2CueArgs) -> Result<(), Cue2CCDError> {
+    let ccd_path = Path::new(&args.filename);
+    let Some(root) = ccd_path.parent() else {
+        return Err(Cue2CCDError::NoParentError {
+            filename: args.filename,
+        });
+    };
+    let Some(stem) = ccd_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+    else {
+        return Err(Cue2CCDError::NoFilenameError {
+            filename: args.filename,
+        });
+    };
+    let output_path = args.output_path.as_deref().map(Path::new).unwrap_or(root);
+    let output_stem = output_path.join(&stem);
+
+    let img_path = ccd_path.with_extension("img");
+    let mut missing_files = vec![];
+    if !img_path.is_file() {
+        missing_files.push(img_path.display().to_string());
+    }
+    let sub_path = ccd_path.with_extension("sub");
+    if (args.lsd || args.sbi) && !sub_path.is_file() {
+        missing_files.push(sub_path.display().to_string());
+    }
+    if !missing_files.is_empty() {
+        return Err(Cue2CCDError::MissingImageFilesError { missing_files });
+    }
+
+    let mut img = File::open(&img_path)?;
+    let mut disc = disc_from_ccd(ccd_path, &mut img)?;
+
+    if args.split {
+        let track_count = disc.tracks.len();
+        img.seek(SeekFrom::Start(0))?;
+        let mut img = BufReader::new(img);
+        for track in disc.tracks.iter_mut() {
+            track.filename = split_filename(&stem, track, track_count);
+            let length = (track.start + track.length - track.indices[0].start) as u64;
+            let mut out_file = BufWriter::new(File::create(output_path.join(&track.filename))?);
+            io::copy(
+                &mut img.by_ref().take(length * RAW_SECTOR_SIZE as u64),
+                &mut out_file,
+            )?;
+            out_file.flush()?;
+        }
+    } else {
+        let img_name = format!("{}.img", stem);
+        // The cuesheet refers to the .img by name, so it needs to be
+        // next to the cuesheet.
+        let img_target = output_path.join(&img_name);
+        if img_target.exists() {
+            if !same_file(&img_path, &img_target) {
+                eprintln!(
+                    "A .img file at path {} already exists; skipping copy",
+                    img_target.display()
+                );
+            }
+        } else {
+            std::fs::copy(&img_path, &img_target)?;
+        }
+        for track in disc.tracks.iter_mut() {
+            track.filename = img_name.clone();
+        }
+    }
+
+    if args.lsd || args.sbi {
+        extract::write_sidecars(&disc, &sub_path, &output_stem, args.lsd, args.sbi)?;
+    }
+
+    let cue_target = output_stem.with_extension("cue");
+    let mut cue_write = File::create(&cue_target)?;
+    disc.write_cue(&mut cue_write)?;
+
+    eprintln!("Conversion complete! Created {}", cue_target.display());
+
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,97 @@
+// Extracts LSD and SBI files from an existing .sub file, preserving any Q
+// subchannel data which can't be regenerated from the disc's layout, such as
+// the sectors deliberately altered by copy protection.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use cdrom::sidecar::{extract_q_subchannels, write_lsd, write_sbi};
+use cdrom::Disc;
+use clap::Args;
+
+use crate::{read_ccd, Cue2CCDError};
+
+#[derive(Args, Debug)]
+pub struct ExtractArgs {
+    /// The .ccd of the disc; its .sub must be next to it
+    filename: String,
+    #[arg(long)]
+    output_path: Option<String>,
+    /// Only write an LSD file
+    #[arg(long, default_value_t = false, conflicts_with = "sbi")]
+    lsd: bool,
+    /// Only write an SBI file
+    #[arg(long, default_value_t = false)]
+    sbi: bool,
+}
+
+/// Writes LSD and/or SBI files for any unusual Q subchannel data in the
+/// .sub at `sub_path`, named after `output_stem`.
+pub fn write_sidecars(
+    disc: &Disc,
+    sub_path: &Path,
+    output_stem: &Path,
+    lsd: bool,
+    sbi: bool,
+) -> Result<(), Cue2CCDError> {
+    let mut sub = BufReader::new(File::open(sub_path)?);
+    let q_subchannels = extract_q_subchannels(disc, &mut sub)?;
+    if q_subchannels.is_empty() {
+        eprintln!(
+            "The subchannel data doesn't contain any unusual Q subchannel data; skipping LSD/SBI"
+        );
+        return Ok(());
+    }
+
+    if lsd {
+        let lsd_target = output_stem.with_extension("lsd");
+        let mut writer = BufWriter::new(File::create(&lsd_target)?);
+        write_lsd(&mut writer, &q_subchannels)?;
+        writer.flush()?;
+        eprintln!("Created {}", lsd_target.display());
+    }
+    if sbi {
+        let sbi_target = output_stem.with_extension("sbi");
+        let mut writer = BufWriter::new(File::create(&sbi_target)?);
+        write_sbi(&mut writer, &q_subchannels)?;
+        writer.flush()?;
+        eprintln!("Created {}", sbi_target.display());
+    }
+
+    Ok(())
+}
+
+pub fn extract(args: ExtractArgs) -> Result<(), Cue2CCDError> {
+    let ccd_path = Path::new(&args.filename);
+    let Some(root) = ccd_path.parent() else {
+        return Err(Cue2CCDError::NoParentError {
+            filename: args.filename,
+        });
+    };
+    let Some(stem) = ccd_path.file_stem() else {
+        return Err(Cue2CCDError::NoFilenameError {
+            filename: args.filename,
+        });
+    };
+    let output_path = args.output_path.as_deref().map(Path::new).unwrap_or(root);
+    let output_stem = output_path.join(stem);
+
+    let sub_path = ccd_path.with_extension("sub");
+    if !sub_path.is_file() {
+        return Err(Cue2CCDError::MissingImageFilesError {
+            missing_files: vec![sub_path.display().to_string()],
+        });
+    }
+
+    let (_, disc) = read_ccd(ccd_path)?;
+    // Write both unless only one was asked for
+    let both = !args.lsd && !args.sbi;
+    write_sidecars(
+        &disc,
+        &sub_path,
+        &output_stem,
+        args.lsd || both,
+        args.sbi || both,
+    )
+}
@@ -1,57 +1,128 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
-
-use cdrom::cue::cd::CD;
-use cdrom::cue::track::{Track, TrackMode};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use cdrom::ccd::Ccd;
+use cdrom::convert::{
+    self, ConversionOptions, ConversionReport, ConversionWarning, ConvertError, Converter,
+    ImgOutcome, ImgPolicy, Sidecars,
+};
+use cdrom::qmode::Placement;
 use cdrom::Disc;
-use cdrom::DiscProtection;
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use miette::{Diagnostic, Result};
 use thiserror::Error;
 
+mod batch;
+mod ccd2cue;
+mod extract;
+mod playlist;
+mod verify;
+
 #[derive(Error, Debug, Diagnostic)]
 enum Cue2CCDError {
     #[error("Couldn't find one or more files specified in the cuesheet.")]
     #[diagnostic(help("Missing files: {}", missing_files.join(", ")))]
     MissingFilesError { missing_files: Vec<String> },
 
+    #[error("Couldn't find one or more files belonging to the CloneCD image.")]
+    #[diagnostic(help(
+        "Missing files: {}. CloneCD images are made up of .ccd, .img and .sub files with the same name.",
+        missing_files.join(", ")
+    ))]
+    MissingImageFilesError { missing_files: Vec<String> },
+
+    #[error("{filename} isn't a valid CCD file")]
+    InvalidCcdError {
+        filename: String,
+        source: cdrom::ccd::CcdError,
+    },
+
+    #[error("{bad_sectors} sector(s) have bad Q subchannel data")]
+    #[diagnostic(help(
+        "Sectors with valid CRCs may have been deliberately altered by copy protection; \
+         these can be preserved as LSD or SBI files with the ccd2cue command."
+    ))]
+    BadSubchannelError { bad_sectors: usize },
+
+    #[error("The .sub file is missing {missing_sectors} sector(s) from the end of the disc")]
+    TruncatedSubchannelError { missing_sectors: i64 },
+
     #[error("Unable to determine the directory {filename} is in!")]
     NoParentError { filename: String },
 
     #[error("Unable to determine the filename portion of {filename}!")]
     NoFilenameError { filename: String },
 
-    // Thrown if SBI file exists but doesn't have the correct SBI header
-    #[error("Invalid SBI file!")]
-    InvalidSBIError {},
-
-    #[error("LSD does not match specified protection!")]
-    InvalidProtectionLSDError {},
-
-    #[error("SBI does not match specified protection!")]
-    InvalidProtectionSBIError {},
-
-    #[error("This tool only supports raw disc images")]
-    #[diagnostic(help("cuesheets containing .wav files are not compatible."))]
-    WaveFile {},
-
-    #[error("This tool only supports raw disc images")]
-    #[diagnostic(help("cuesheets containing ISOs or other non-raw data are not compatible."))]
-    CookedData {},
-
     #[error(transparent)]
     IO(#[from] std::io::Error),
 
     #[error(transparent)]
-    Cue(#[from] std::ffi::NulError),
+    #[diagnostic(transparent)]
+    Convert(#[from] ConvertError),
+
+    #[error("{filename} isn't a directory")]
+    #[diagnostic(help(
+        "--recursive converts every cuesheet in a directory and its subdirectories."
+    ))]
+    NotADirectoryError { filename: String },
+
+    #[error("Couldn't find one or more discs listed in the playlist.")]
+    #[diagnostic(help("Missing files: {}", missing_files.join(", ")))]
+    MissingDiscsError { missing_files: Vec<String> },
+
+    #[error("{filename} doesn't list any cuesheets")]
+    EmptyPlaylistError { filename: String },
+
+    #[error("--lsd and --sbi can't be used with a playlist")]
+    #[diagnostic(help(
+        "An LSD or SBI file with the same name as each disc's cuesheet is used automatically."
+    ))]
+    PlaylistSidecarError {},
+
+    #[error("{}", batch::describe_failures(*total, failures))]
+    #[diagnostic(help("Everything else was converted; run again once these are fixed and only they will be retried."))]
+    BatchError {
+        total: usize,
+        #[related]
+        failures: Vec<batch::BatchFailure>,
+    },
 }
 
 #[derive(Clone, Debug, ValueEnum)]
 enum ProtectionType {
     #[clap(name = "discguard")]
     DiscGuard,
+    #[clap(name = "libcrypt")]
+    LibCrypt,
+}
+
+impl ProtectionType {
+    fn convert_type(&self) -> convert::ProtectionType {
+        match self {
+            ProtectionType::DiscGuard => convert::ProtectionType::DiscGuard,
+            ProtectionType::LibCrypt => convert::ProtectionType::LibCrypt,
+        }
+    }
+}
+
+// LibCrypt keys are conventionally written in hex
+fn parse_libcrypt_key(key: &str) -> Result<u16, std::num::ParseIntError> {
+    let key = key
+        .strip_prefix("0x")
+        .or_else(|| key.strip_prefix("0X"))
+        .unwrap_or(key);
+    u16::from_str_radix(key, 16)
+}
+
+// Either "standard", or a number of frames
+fn parse_mcn_placement(placement: &str) -> Result<Placement, String> {
+    if placement.eq_ignore_ascii_case("standard") {
+        return Ok(Placement::Standard);
+    }
+    match placement.parse() {
+        Ok(0) | Err(_) => Err("expected \"standard\" or a number of frames".to_string()),
+        Ok(n) => Ok(Placement::EveryNth(n)),
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -59,133 +130,79 @@ enum ProtectionType {
     author,
     version,
     about,
-    long_about = "Generate CCD and SUB files from BIN/CUE"
+    long_about = "Generate CCD and SUB files from BIN/CUE",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Args {
-    filename: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(required = true)]
+    filename: Option<String>,
     #[arg(long, default_value_t = false)]
     skip_img_copy: bool,
     #[arg(long)]
     output_path: Option<String>,
     #[arg(long, value_enum)]
     pub protection_type: Option<ProtectionType>,
+    /// The 16-bit LibCrypt key, in hex, for use when there's no LSD or SBI
+    #[arg(long, value_parser = parse_libcrypt_key, requires = "protection_type")]
+    pub libcrypt_key: Option<u16>,
+    /// The LSD to use; defaults to one with the same name as the cuesheet
+    #[arg(long, conflicts_with = "no_sidecars")]
+    pub lsd: Option<String>,
+    /// The SBI to use; defaults to one with the same name as the cuesheet
+    #[arg(long, conflicts_with = "no_sidecars")]
+    pub sbi: Option<String>,
+    /// Ignore any LSD or SBI next to the cuesheet
+    #[arg(long, default_value_t = false)]
+    pub no_sidecars: bool,
+    /// Use damaged LSD and SBI files anyway, warning about their problems
+    #[arg(long, default_value_t = false)]
+    pub lenient: bool,
+    /// Where to place the catalog number's Q subchannel frames:
+    /// "standard" for one every 100 frames, or a number N for every Nth frame.
+    /// ISRC frames go halfway between them
+    #[arg(long, value_parser = parse_mcn_placement, default_value = "standard")]
+    pub mcn_placement: Placement,
+    /// Convert every cuesheet in the directory FILENAME and its subdirectories,
+    /// mirroring their layout under --output-path if it's given. Cuesheets
+    /// which already have a .ccd are skipped
+    #[arg(long, default_value_t = false, conflicts_with_all = ["lsd", "sbi"])]
+    pub recursive: bool,
+    /// How many cuesheets to convert at once with --recursive
+    #[arg(long, default_value = "1", requires = "recursive")]
+    pub jobs: NonZeroUsize,
+    /// When FILENAME is a ZIP archive containing more than one cuesheet,
+    /// the one to convert, either by its path within the archive or by
+    /// just its filename
+    #[arg(long, conflicts_with = "recursive")]
+    pub cuesheet: Option<String>,
 }
 
-fn validate_mode(tracks: &[Track]) -> Result<(), Cue2CCDError> {
-    for track in tracks {
-        if track.get_filename().ends_with(".wav") {
-            return Err(Cue2CCDError::WaveFile {});
-        }
-        match track.get_mode() {
-            TrackMode::Mode1 | TrackMode::Mode2 | TrackMode::Mode2Form1 | TrackMode::Mode2Form2 => {
-                return Err(Cue2CCDError::CookedData {});
-            }
-            _ => (),
-        }
-    }
-    Ok(())
-}
-
-/// Fetches unique tracks from the list of tracks.
-/// If the same track appears multiple times in a row,
-/// returns only a single copy.
-fn get_unique_tracks(tracks: &[Track]) -> Vec<String> {
-    let mut files = vec![];
-
-    for track in tracks.iter() {
-        let filename = track.get_filename();
-        if files.last() == Some(&filename) {
-            continue;
-        }
-        files.push(filename);
-    }
-
-    files
-}
-
-// LSD File Format:
-// The file consists of subQ data, specifically consisting of the actual AMSF that the current subQ
-// was read from, followed by all 12 bytes of subQ data. LSD is definitively better as a file
-// format for storing subchannel data discrepancies as opposed to SBI, which forces you to
-// generate the CRC16 yourself (something that is a huge problem for SecuROM and LibCrypt if
-// you're aiming for accuracy) and ideally should always be preferred if possible.
-fn generate_lsd_data(raw_lsd_data: Vec<u8>) -> Result<HashMap<i64, Vec<u8>>, Cue2CCDError> {
-    // LSD files have never been defined in the cuesheet, and programs (mainly just PS1
-    // emulators so far) that make use of them simply check if there's an LSD file with the
-    // same basename next to the .cue. If one exists, they use it, otherwise they don't.
-    // It seems best to keep in line with this behavior
-
-    let mut hash_map: HashMap<i64, Vec<u8>> = HashMap::new();
-    // should always be multiple of 15
-    for chunk in raw_lsd_data.chunks(15) {
-        let mut q = vec![0; 12];
-        // These don't really need to be muts, but, they should always be getting set in the
-        // enumeration, and it makes things easier to not have to pass them as options
-        let mut m: i64 = 0;
-        let mut s: i64 = 0;
-        let mut f: i64 = 0;
-        for (byte_index, &item) in chunk.iter().enumerate() {
-            match byte_index {
-                0 => m = item as i64,
-                1 => s = item as i64,
-                2 => f = item as i64,
-                _ => q[byte_index - 3] = item,
-            }
-        }
-        hash_map.insert(cdrom::amsf_to_asec(m, s, f), q);
-    }
-    Ok(hash_map)
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a BIN/CUE from a CloneCD CCD/IMG/SUB
+    Ccd2cue(ccd2cue::Ccd2CueArgs),
+    /// Check the Q subchannel data in an existing .sub file
+    Verify(verify::VerifyArgs),
+    /// Extract LSD and SBI files from an existing .sub file
+    Extract(extract::ExtractArgs),
 }
 
-// SBI File Format:
-// Starts with header 0x53 0x42 0x49 0x00 ('S' 'B' 'I' '0x00')
-// The entire rest of the file consists of subQ data, specifically consisting of the actual
-// AMSF that the current subQ was read from, followed by a dummy 0x01 byte, followed by the first
-// 10 bytes of that subQ (so, everything but the CRC16). The exclusion of the CRC16 is obviously
-// annoying, *especially* for SecuROM and LibCrypt. LSD is a better file format, but at the
-// moment, redump will only generate LSD files for PS1 discs, and we do not have the power to
-// change the website; so, until a successor website exists, SBI support is necessary. It's
-// also still preferred by a lot of people and emulators for PS1 for some reason, despite
-// being worse than LSD.
-fn generate_sbi_data(raw_sbi_data: Vec<u8>) -> Result<HashMap<i64, Vec<u8>>, Cue2CCDError> {
-    // SBI files have never been defined in the cuesheet, and programs (mainly just PS1
-    // emulators so far) that make use of them simply check if there's an SBI file with the
-    // same basename next to the .cue. If one exists, they use it, otherwise they don't.
-    // It seems best to keep in line with this behavior
-
-    let (header, data) = raw_sbi_data.split_at(4);
-    let mut hash_map: HashMap<i64, Vec<u8>> = HashMap::new();
-    if header != [83, 66, 73, 00] {
-        // Checks for required [S][B][I][0x00] header
-        return Err(Cue2CCDError::InvalidSBIError {});
-    }
-    // should always be multiple of 14
-    for chunk in data.chunks(14) {
-        let mut q = vec![0; 10];
-        // These don't really need to be muts, but, they should always be getting set in the
-        // enumeration, and it makes things easier to not have to pass them as options
-        let mut m: i64 = 0;
-        let mut s: i64 = 0;
-        let mut f: i64 = 0;
-        for (byte_index, &item) in chunk.iter().enumerate() {
-            match byte_index {
-                0 => m = item as i64,
-                1 => s = item as i64,
-                2 => f = item as i64,
-                // Index 3 excluded to ignore dummy 0x01 byte
-                3 => (),
-                _ => q[byte_index - 4] = item,
-            }
+fn read_ccd(path: &Path) -> Result<(Ccd, Disc), Cue2CCDError> {
+    let ccd = Ccd::parse(&std::fs::read_to_string(path)?).map_err(|source| {
+        Cue2CCDError::InvalidCcdError {
+            filename: path.display().to_string(),
+            source,
         }
-        // Unlike LSD, SBI is missing the CRC16, so we have to do that
-        // ourselves.
-        let crc = cdrom::crc16(&q, cdrom::CRC16_INITIAL_CRC);
-        q.push(((crc >> 8) & 0xFF) as u8);
-        q.push((crc & 0xFF) as u8);
+    })?;
+    let disc = Disc::from_ccd(&ccd).map_err(|source| Cue2CCDError::InvalidCcdError {
+        filename: path.display().to_string(),
+        source,
+    })?;
 
-        hash_map.insert(cdrom::amsf_to_asec(m, s, f), q);
-    }
-    Ok(hash_map)
+    Ok((ccd, disc))
 }
 
 fn main() -> Result<(), miette::Report> {
@@ -195,131 +212,112 @@ fn main() -> Result<(), miette::Report> {
 
 fn work() -> Result<(), Cue2CCDError> {
     let args = Args::parse();
-
-    let Some(root) = Path::new(&args.filename).parent() else {
-        return Err(Cue2CCDError::NoParentError {
-            filename: args.filename,
-        });
-    };
-    let Some(basename) = Path::new(&args.filename).file_name() else {
-        return Err(Cue2CCDError::NoFilenameError {
-            filename: args.filename,
-        });
-    };
-    let path;
-    let output_path;
-    if let Some(p) = args.output_path {
-        path = p;
-        output_path = Path::new(&path);
-    } else {
-        output_path = root;
+    match args.command {
+        Some(Command::Ccd2cue(args)) => return ccd2cue::convert(args),
+        Some(Command::Verify(args)) => return verify::verify(args),
+        Some(Command::Extract(args)) => return extract::extract(args),
+        None => (),
     }
-    // Provides a pattern to build output filenames from
-    let output_stem = output_path.join(basename);
-
-    let cue_sheet = std::fs::read_to_string(&args.filename)?;
-
-    let cd = CD::parse(cue_sheet)?;
-
-    let tracks = cd.tracks();
-
-    // We validate that the track modes are compatible. BIN/CUE can be
-    // a variety of different formats, including WAVE files and "cooked"
-    // tracks with no error correction metadata. We need all raw files in
-    // order to be able to merge into a CloneCD image.
-    // In the future, it may be nice to support actually converting tracks
-    // into the supported format, but right now that's out of scope.
-    validate_mode(&tracks)?;
-
-    let files = get_unique_tracks(&tracks);
-    let missing_files = files
-        .iter()
-        .filter(|f| !root.join(f).is_file())
-        .cloned()
-        .collect::<Vec<String>>();
-    if !missing_files.is_empty() {
-        return Err(Cue2CCDError::MissingFilesError { missing_files });
+    // Required whenever a subcommand isn't used
+    let Some(filename) = args.filename.clone() else {
+        unreachable!();
+    };
+
+    if args.recursive {
+        return batch::convert_tree(&args, Path::new(&filename));
     }
-    let mut preconstructed_q_subcodes: HashMap<i64, Vec<u8>> = Default::default();
-
-    let mut chosen_protection_type: Option<DiscProtection> = None;
-    // TODO: #1 - see about making lsd/sbi extension checks not case sensitive
-    // TODO: #2 - verify expected SBI/LSD sizes?
-    // TODO: #3 - choose protection based off of lsd/sbi size if lsd/sbi is present and a
-    // TODO: protection wasn't chosen? This can't be  done universally, but it can be done for a
-    // TODO: lot of stuff. That could also be an issue for anyone who wants to provide an LSD/SBI
-    // TODO: for a non-protection related reason and happens to hit one of the exact sizes/contents
-    // TODO: needed, but that is a use case that does not currently exist.
-    if Path::new(&output_stem.with_extension("lsd")).exists() {
-        // LSD files are very small, so it seems best to read the whole thing in first?
-        let temp_hashmap = generate_lsd_data(std::fs::read(Path::new(
-            &output_stem.with_extension("lsd"),
-        ))?)?;
-        let len = temp_hashmap.len();
-        if len == 76 {
-            chosen_protection_type = Some(DiscProtection::DiscGuardScheme2);
-        } else if len == 600 {
-            chosen_protection_type = Some(DiscProtection::DiscGuardScheme1);
-        } else if matches!(args.protection_type, Some(ProtectionType::DiscGuard)) {
-            return Err(Cue2CCDError::InvalidProtectionLSDError {});
-        }
-        preconstructed_q_subcodes = temp_hashmap;
-    } else if Path::new(&output_stem.with_extension("sbi")).exists() {
-        // SBI files are very small, so it seems best to read the whole thing in first?
-        let temp_hashmap = generate_sbi_data(std::fs::read(Path::new(
-            &output_stem.with_extension("sbi"),
-        ))?)?;
-        let len = temp_hashmap.len();
-        if len == 76 {
-            chosen_protection_type = Some(DiscProtection::DiscGuardScheme2);
-        } else if len == 600 {
-            chosen_protection_type = Some(DiscProtection::DiscGuardScheme1);
-        } else if matches!(args.protection_type, Some(ProtectionType::DiscGuard)) {
-            return Err(Cue2CCDError::InvalidProtectionSBIError {});
-        }
-        preconstructed_q_subcodes = temp_hashmap;
-    } else if matches!(args.protection_type, Some(ProtectionType::DiscGuard)) {
-        chosen_protection_type = Some(DiscProtection::DiscGuardScheme2);
+    if playlist::is_playlist(Path::new(&filename)) {
+        return playlist::convert_playlist(&args, Path::new(&filename));
     }
 
-    let sub_target = output_stem.with_extension("sub");
-    let mut sub_write = File::create(sub_target)?;
+    let output_dir = args.output_path.as_ref().map(PathBuf::from);
+    let options = conversion_options(&args, Path::new(&filename), output_dir);
+    let report = Converter::new(options).convert()?;
+    print_report(&report);
+    eprintln!("Conversion complete! Created {}", report.ccd.display());
+
+    Ok(())
+}
 
-    let disc = Disc::from_cuesheet(cd, root);
-    for sector in disc.sectors() {
-        sub_write.write_all(
-            &sector.generate_subchannel(&chosen_protection_type, &preconstructed_q_subcodes),
-        )?;
+// The options given on the command line, for converting the cuesheet at
+// `cue_path` into `output_dir`.
+fn conversion_options(
+    args: &Args,
+    cue_path: &Path,
+    output_dir: Option<PathBuf>,
+) -> ConversionOptions {
+    let mut options = ConversionOptions::new(cue_path)
+        .lenient(args.lenient)
+        .mcn_placement(args.mcn_placement);
+    if let Some(output_dir) = output_dir {
+        options = options.output_dir(output_dir);
+    }
+    if let Some(cuesheet) = &args.cuesheet {
+        options = options.archive_cuesheet(cuesheet);
+    }
+    if args.skip_img_copy {
+        options = options.img_policy(ImgPolicy::Skip);
+    }
+    if let Some(protection_type) = &args.protection_type {
+        options = options.protection_type(protection_type.convert_type());
+    }
+    if let Some(key) = args.libcrypt_key {
+        options = options.libcrypt_key(key);
+    }
+    if args.no_sidecars {
+        options = options.sidecars(Sidecars::Ignore);
+    } else if args.lsd.is_some() || args.sbi.is_some() {
+        // Only use what we were given
+        options = options.sidecars(Sidecars::Given {
+            lsd: args.lsd.as_ref().map(PathBuf::from),
+            sbi: args.sbi.as_ref().map(PathBuf::from),
+        });
     }
 
-    let ccd_target = output_stem.with_extension("ccd");
-    let mut ccd_write = File::create(ccd_target)?;
-    disc.write_ccd(&mut ccd_write)?;
+    options
+}
 
-    if !args.skip_img_copy {
-        let img_target = output_stem.with_extension("img");
-        if img_target.exists() {
+// Tells the user about everything in the report except the files written
+fn print_report(report: &ConversionReport) {
+    if let Some(sidecars) = &report.sidecars {
+        if let (Some(lsd), Some(sbi)) = (&sidecars.lsd, &sidecars.sbi) {
+            eprintln!(
+                "Found both {} and {}; merged them, using the LSD where they disagree",
+                lsd.display(),
+                sbi.display()
+            );
+        }
+        if let Some(detected) = &sidecars.detected {
             eprintln!(
-                "A .img file at path {} already exists; skipping copy",
-                img_target.as_path().display()
+                "Detected {} from the {} with {} confidence: {}",
+                detected.protection,
+                sidecars.description(),
+                detected.confidence,
+                detected.reason
             );
-        } else {
-            let mut out_file = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&img_target)?;
-            for fname in files {
-                let mut in_file = File::open(root.join(&fname))?;
-                std::io::copy(&mut in_file, &mut out_file)?;
-                out_file.flush()?;
-            }
         }
     }
+    for warning in &report.warnings {
+        match warning {
+            ConversionWarning::MissingLibCryptKey => eprintln!(
+                "No LSD or SBI file found, and no --libcrypt-key given; \
+                 the LibCrypt key will probably be wrong"
+            ),
+            warning => eprintln!("Warning: {}", warning),
+        }
+    }
+    if let ImgOutcome::AlreadyExists(img) = &report.img {
+        eprintln!(
+            "A .img file at path {} already exists; skipping copy",
+            img.display()
+        );
+    }
+}
 
-    eprintln!(
-        "Conversion complete! Created {}",
-        output_stem.with_extension("ccd").display()
-    );
-
-    Ok(())
+// Creates a scratch directory for a test, unique to this run.
+#[cfg(test)]
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cue2ccd-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
 }
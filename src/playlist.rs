@@ -0,0 +1,213 @@
+// Multi-disc games are usually distributed with an .m3u playlist listing
+// the cuesheet of each disc, one per line, so that emulators can switch
+// between them. Converting the playlist converts every disc in it and
+// writes a new playlist listing the .ccd files instead. Anything else in the
+// playlist, such as comments or #EXTINF lines, is copied as-is.
+
+use std::path::{Path, PathBuf};
+
+use cdrom::convert::Converter;
+
+use crate::{conversion_options, print_report, Args, Cue2CCDError};
+
+fn is_cuesheet(entry: &str) -> bool {
+    !entry.starts_with('#')
+        && Path::new(entry)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("cue"))
+}
+
+/// Whether `path` looks like a playlist rather than a cuesheet.
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("m3u"))
+}
+
+/// Converts every cuesheet listed in the playlist at `path`, then writes a
+/// playlist of the converted discs.
+pub fn convert_playlist(args: &Args, path: &Path) -> Result<(), Cue2CCDError> {
+    let filename = path.display().to_string();
+    if args.lsd.is_some() || args.sbi.is_some() {
+        return Err(Cue2CCDError::PlaylistSidecarError {});
+    }
+    let (Some(root), Some(basename)) = (path.parent(), path.file_name()) else {
+        return Err(Cue2CCDError::NoFilenameError { filename });
+    };
+
+    let playlist = std::fs::read_to_string(path)?;
+    // Some playlists start with a UTF-8 byte order mark
+    let playlist = playlist.trim_start_matches('\u{FEFF}');
+    let entries = playlist
+        .lines()
+        .map(str::trim)
+        .filter(|line| is_cuesheet(line))
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        return Err(Cue2CCDError::EmptyPlaylistError { filename });
+    }
+    let missing_files = entries
+        .iter()
+        .filter(|entry| !root.join(entry).is_file())
+        .map(|entry| entry.to_string())
+        .collect::<Vec<_>>();
+    if !missing_files.is_empty() {
+        return Err(Cue2CCDError::MissingDiscsError { missing_files });
+    }
+
+    let output_root = args.output_path.as_ref().map(PathBuf::from);
+    let mut converted = vec![];
+    for entry in entries {
+        let cue_path = root.join(entry);
+        // Discs in subdirectories keep their place relative to the playlist
+        let output_dir = output_root
+            .as_ref()
+            .map(|output_root| match Path::new(entry).parent() {
+                Some(parent) if Path::new(entry).is_relative() => output_root.join(parent),
+                _ => output_root.clone(),
+            });
+        if let Some(output_dir) = &output_dir {
+            std::fs::create_dir_all(output_dir)?;
+        }
+
+        eprintln!("Converting {}", cue_path.display());
+        let options = conversion_options(args, &cue_path, output_dir);
+        let report = Converter::new(options).convert()?;
+        print_report(&report);
+
+        let ccd = if Path::new(entry).is_relative() {
+            Path::new(entry).with_extension("ccd")
+        } else {
+            report.ccd
+        };
+        converted.push((entry, ccd));
+    }
+
+    let mut lines = vec![];
+    for line in playlist.lines() {
+        match converted.iter().find(|(entry, _)| *entry == line.trim()) {
+            Some((_, ccd)) => lines.push(ccd.display().to_string()),
+            None => lines.push(line.to_string()),
+        }
+    }
+    let line_ending = if playlist.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+
+    // Never replace the playlist we were given
+    let output_dir = output_root.as_deref().unwrap_or(root);
+    let mut target = output_dir.join(basename);
+    if same_file(&target, path) {
+        let stem = path.file_stem().unwrap_or(basename).to_string_lossy();
+        target = output_dir.join(format!("{} (CloneCD).m3u", stem));
+    }
+    std::fs::write(&target, lines.join(line_ending) + line_ending)?;
+
+    eprintln!(
+        "Conversion complete! Converted {} discs and created {}",
+        converted.len(),
+        target.display()
+    );
+
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use clap::Parser;
+
+    use super::{convert_playlist, is_cuesheet};
+    use crate::{temp_dir, Args};
+
+    // Copies the test disc into `dir` as `name`.cue and `name`.bin.
+    fn copy_disc(dir: &Path, name: &str) -> PathBuf {
+        let bincue = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("dataplusaudio")
+            .join("bincue");
+        std::fs::create_dir_all(dir).unwrap();
+        let cue_sheet = std::fs::read_to_string(bincue.join("disc.cue")).unwrap();
+        let cue_path = dir.join(format!("{}.cue", name));
+        std::fs::write(
+            &cue_path,
+            cue_sheet.replace("disc.bin", &format!("{}.bin", name)),
+        )
+        .unwrap();
+        std::fs::copy(bincue.join("disc.bin"), dir.join(format!("{}.bin", name))).unwrap();
+        cue_path
+    }
+
+    fn args(extra: &[&str]) -> Args {
+        Args::parse_from(
+            ["cue2ccd", "--skip-img-copy", "playlist.m3u"]
+                .iter()
+                .chain(extra),
+        )
+    }
+
+    #[test]
+    fn test_is_cuesheet() {
+        assert!(is_cuesheet("Disc 1/game.cue"));
+        assert!(is_cuesheet("GAME.CUE"));
+        assert!(!is_cuesheet("#game.cue"));
+        assert!(!is_cuesheet("#EXTM3U"));
+        assert!(!is_cuesheet("game.ccd"));
+    }
+
+    #[test]
+    fn test_convert_playlist() {
+        let root = temp_dir("playlist");
+        copy_disc(&root.join("Disc 1"), "game");
+        let elsewhere = copy_disc(&temp_dir("playlist-elsewhere"), "other");
+        let playlist = root.join("game.m3u");
+        std::fs::write(
+            &playlist,
+            format!(
+                "\u{FEFF}#EXTM3U\r\nDisc 1/game.cue\r\n{}\r\n",
+                elsewhere.display()
+            ),
+        )
+        .unwrap();
+
+        // Written next to the original, which is left alone
+        convert_playlist(&args(&[]), &playlist).unwrap();
+        assert!(root.join("Disc 1").join("game.ccd").is_file());
+        assert!(elsewhere.with_extension("ccd").is_file());
+        assert_eq!(
+            std::fs::read_to_string(root.join("game (CloneCD).m3u")).unwrap(),
+            format!(
+                "#EXTM3U\r\nDisc 1/game.ccd\r\n{}\r\n",
+                elsewhere.with_extension("ccd").display()
+            )
+        );
+
+        // With --output-path, relative entries keep their subdirectories,
+        // while absolute ones go straight into it
+        let output = temp_dir("playlist-output");
+        let output_arg = output.to_str().unwrap();
+        convert_playlist(&args(&["--output-path", output_arg]), &playlist).unwrap();
+        assert!(output.join("Disc 1").join("game.ccd").is_file());
+        assert!(output.join("other.ccd").is_file());
+        assert_eq!(
+            std::fs::read_to_string(output.join("game.m3u")).unwrap(),
+            format!(
+                "#EXTM3U\r\nDisc 1/game.ccd\r\n{}\r\n",
+                output.join("other.ccd").display()
+            )
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+        std::fs::remove_dir_all(elsewhere.parent().unwrap()).unwrap();
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
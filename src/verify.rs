@@ -0,0 +1,166 @@
+// Checks the Q subchannel data in an existing .sub file, reporting every
+// sector that doesn't match what we'd generate for the disc.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use cdrom::convert;
+use cdrom::verify::{verify_q_subchannel, QMismatch, QPosition, VerifyReport};
+use cdrom::Disc;
+use clap::Args;
+use serde::Serialize;
+
+use crate::{read_ccd, Cue2CCDError};
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// The .ccd or .cue describing the disc
+    filename: String,
+    /// The .sub file to check; defaults to the .sub next to the .ccd or .cue
+    #[arg(long)]
+    sub: Option<String>,
+    /// Print the report as JSON instead of a table
+    #[arg(long, default_value_t = false)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct JsonReport {
+    sectors_checked: i64,
+    sectors_missing: i64,
+    mismatches: Vec<JsonMismatch>,
+}
+
+#[derive(Serialize)]
+struct JsonMismatch {
+    lba: i64,
+    crc_valid: bool,
+    expected: JsonQ,
+    actual: JsonQ,
+}
+
+#[derive(Serialize)]
+struct JsonQ {
+    adr: u8,
+    track: u8,
+    index: u8,
+    relative_msf: String,
+    absolute_msf: String,
+    q: String,
+}
+
+impl JsonQ {
+    fn new(q: &[u8; 12]) -> JsonQ {
+        let position = QPosition::decode(q);
+        JsonQ {
+            adr: position.adr,
+            track: position.track,
+            index: position.index,
+            relative_msf: position.relative.to_string(),
+            absolute_msf: position.absolute.to_string(),
+            q: q.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+}
+
+fn read_disc(path: &Path) -> Result<Disc, Cue2CCDError> {
+    let is_ccd = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ccd"));
+    if is_ccd {
+        return Ok(read_ccd(path)?.1);
+    }
+
+    Ok(convert::read_cuesheet(path)?)
+}
+
+fn format_row(mismatch: &QMismatch) -> String {
+    let expected = QPosition::decode(&mismatch.expected);
+    let actual = QPosition::decode(&mismatch.actual);
+    format!(
+        "{:>7}  {:<3}  {:>5} {:>5}  {}  {}  {:>5} {:>5}  {}  {}",
+        mismatch.sector,
+        if mismatch.crc_valid { "ok" } else { "bad" },
+        expected.track,
+        expected.index,
+        expected.relative,
+        expected.absolute,
+        actual.track,
+        actual.index,
+        actual.relative,
+        actual.absolute,
+    )
+}
+
+fn print_table(report: &VerifyReport) {
+    if !report.mismatches.is_empty() {
+        println!("              Expected                         Actual");
+        println!("    LBA  CRC  Track Index  Relative  Absolute  Track Index  Relative  Absolute");
+        for mismatch in &report.mismatches {
+            println!("{}", format_row(mismatch));
+        }
+        println!();
+    }
+
+    let bad_crcs = report.mismatches.iter().filter(|m| !m.crc_valid).count();
+    println!(
+        "Checked {} sectors: {} with unexpected Q subchannel data, {} of which have bad CRCs",
+        report.sectors_checked,
+        report.mismatches.len(),
+        bad_crcs
+    );
+}
+
+pub fn verify(args: VerifyArgs) -> Result<(), Cue2CCDError> {
+    let path = Path::new(&args.filename);
+    let sub_path = args
+        .sub
+        .map(Into::into)
+        .unwrap_or_else(|| path.with_extension("sub"));
+    if !sub_path.is_file() {
+        return Err(Cue2CCDError::MissingFilesError {
+            missing_files: vec![sub_path.display().to_string()],
+        });
+    }
+
+    let disc = read_disc(path)?;
+    let mut sub = BufReader::new(File::open(&sub_path)?);
+    let report = verify_q_subchannel(&disc, &mut sub)?;
+
+    if args.json {
+        let json = JsonReport {
+            sectors_checked: report.sectors_checked,
+            sectors_missing: report.sectors_missing,
+            mismatches: report
+                .mismatches
+                .iter()
+                .map(|m| JsonMismatch {
+                    lba: m.sector,
+                    crc_valid: m.crc_valid,
+                    expected: JsonQ::new(&m.expected),
+                    actual: JsonQ::new(&m.actual),
+                })
+                .collect(),
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json).expect("report is always serializable")
+        );
+    } else {
+        print_table(&report);
+    }
+
+    if report.sectors_missing > 0 {
+        return Err(Cue2CCDError::TruncatedSubchannelError {
+            missing_sectors: report.sectors_missing,
+        });
+    }
+    if !report.mismatches.is_empty() {
+        return Err(Cue2CCDError::BadSubchannelError {
+            bad_sectors: report.mismatches.len(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,11 @@
+TITLE "Album"
+FILE "../bincue/disc.bin" BINARY
+  TRACK 01 MODE1/2352
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Song"
+    INDEX 00 00:04:16
+    INDEX 01 00:06:16
+  TRACK 03 AUDIO
+    INDEX 00 00:07:16
+    INDEX 01 00:09:16
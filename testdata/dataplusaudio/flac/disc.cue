@@ -0,0 +1,6 @@
+FILE "audio.flac" WAVE
+  TRACK 01 AUDIO
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 00 00:01:00
+    INDEX 01 00:01:50
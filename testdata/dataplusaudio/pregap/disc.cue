@@ -0,0 +1,11 @@
+FILE "data.bin" BINARY
+  TRACK 01 MODE1/2352
+    INDEX 01 00:00:00
+FILE "track02.bin" BINARY
+  TRACK 02 AUDIO
+    PREGAP 00:02:00
+    INDEX 01 00:00:00
+FILE "track03.bin" BINARY
+  TRACK 03 AUDIO
+    PREGAP 00:02:00
+    INDEX 01 00:00:00
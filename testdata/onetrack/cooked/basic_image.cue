@@ -0,0 +1,3 @@
+FILE "basic_image.bin" BINARY
+  TRACK 01 MODE1/2048
+    INDEX 01 00:00:00